@@ -0,0 +1,128 @@
+use std::env;
+
+use crossterm::style::Color;
+
+/// Levels of each channel in the 6x6x6 cube of the 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The 16 basic colors with their usual xterm values.
+const BASIC_COLORS: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// How many colors the terminal can show.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorSupport {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    /// No colors at all; only attributes such as bold and reverse.
+    None,
+}
+
+impl ColorSupport {
+    /// Guesses the support from `NO_COLOR`, `COLORTERM` and `TERM`.
+    pub fn detect() -> Self {
+        let var = |name| env::var(name).unwrap_or_default();
+        if !var("NO_COLOR").is_empty() {
+            return Self::None;
+        }
+        let term = var("TERM");
+        if matches!(var("COLORTERM").as_str(), "truecolor" | "24bit") || term.ends_with("-direct") {
+            Self::TrueColor
+        } else if term.contains("256color") {
+            Self::Ansi256
+        } else if term.is_empty() || term == "dumb" {
+            Self::None
+        } else {
+            Self::Ansi16
+        }
+    }
+
+    /// Parses the `color_mode` setting; `auto` means detect.
+    pub fn parse(name: &str) -> Result<Option<Self>, String> {
+        match name {
+            "auto" => Ok(None),
+            "truecolor" => Ok(Some(Self::TrueColor)),
+            "256" => Ok(Some(Self::Ansi256)),
+            "16" => Ok(Some(Self::Ansi16)),
+            "none" => Ok(Some(Self::None)),
+            _ => Err("must be \"auto\", \"truecolor\", \"256\", \"16\" or \"none\"".to_string()),
+        }
+    }
+
+    /// The closest color the terminal can show to `color`.
+    pub fn map(self, color: Color) -> Color {
+        match (self, color) {
+            (_, Color::Reset) | (Self::TrueColor, _) => color,
+            (Self::None, _) => Color::Reset,
+            (Self::Ansi256, Color::Rgb { r, g, b }) => Color::AnsiValue(nearest_256((r, g, b))),
+            (Self::Ansi16, Color::Rgb { r, g, b }) => nearest_basic((r, g, b)),
+            (Self::Ansi16, Color::AnsiValue(value)) => nearest_basic(ansi_to_rgb(value)),
+            (_, color) => color,
+        }
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let channel = |a: u8, b: u8| u32::from(a.abs_diff(b)).pow(2);
+    channel(r1, r2) + channel(g1, g2) + channel(b1, b2)
+}
+
+fn nearest_basic(rgb: (u8, u8, u8)) -> Color {
+    BASIC_COLORS
+        .iter()
+        .min_by_key(|(_, basic)| distance(rgb, *basic))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
+
+/// The nearest entry of the color cube or the gray ramp.
+fn nearest_256((r, g, b): (u8, u8, u8)) -> u8 {
+    let level = |channel: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&index| CUBE_LEVELS[index].abs_diff(channel))
+            .unwrap_or(0) as u8
+    };
+    let cube = 16 + 36 * level(r) + 6 * level(g) + level(b);
+    let average = ((u16::from(r) + u16::from(g) + u16::from(b)) / 3) as u8;
+    let gray = 232 + (average.saturating_sub(3) / 10).min(23);
+    if distance((r, g, b), ansi_to_rgb(gray)) < distance((r, g, b), ansi_to_rgb(cube)) {
+        gray
+    } else {
+        cube
+    }
+}
+
+/// The RGB value of an entry of the 256-color palette.
+fn ansi_to_rgb(value: u8) -> (u8, u8, u8) {
+    match value {
+        0..=15 => BASIC_COLORS[value as usize].1,
+        16..=231 => {
+            let index = value - 16;
+            (
+                CUBE_LEVELS[(index / 36) as usize],
+                CUBE_LEVELS[(index / 6 % 6) as usize],
+                CUBE_LEVELS[(index % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (value - 232);
+            (level, level, level)
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use toml::{Table, Value};
+
+use crate::{
+    color::ColorSupport,
+    keymap::{parse_sequence, Action, KeyChord, Profile},
+    theme::{Style, STYLE_NAMES},
+};
+
+const PROJECT_CONFIG: &str = ".te.toml";
+
+/// Names of the settings `Config::set` understands.
+pub const SETTINGS: &[&str] = &[
+    "tab_width",
+    "soft_wrap",
+    "line_numbers",
+    "tab_bar",
+    "quit_times",
+    "message_timeout",
+    "autosave",
+    "reflow_column",
+    "theme",
+    "color_mode",
+    "styles",
+    "keybindings",
+    "keys",
+];
+
+/// Editor settings, read from `$XDG_CONFIG_HOME/te/config.toml` and then
+/// from the nearest `.te.toml` in the current directory or its parents.
+pub struct Config {
+    pub tab_width: usize,
+    pub soft_wrap: bool,
+    pub line_numbers: bool,
+    pub tab_bar: bool,
+    pub quit_times: u8,
+    pub message_timeout: Duration,
+    /// Idle time after which modified buffers are saved, if enabled.
+    pub autosave: Option<Duration>,
+    pub reflow_column: usize,
+    pub theme: String,
+    /// Forced color support; detected from the environment if None.
+    pub color_mode: Option<ColorSupport>,
+    /// Style overrides from the `[styles]` table, applied on top of the theme.
+    pub styles: Vec<(String, Value)>,
+    pub keybindings: Profile,
+    /// Key bindings from the `[keys]` table; `None` removes a default binding.
+    pub keys: Vec<(Vec<KeyChord>, Option<Action>)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            soft_wrap: false,
+            line_numbers: false,
+            tab_bar: true,
+            quit_times: 1,
+            message_timeout: Duration::from_secs(5),
+            autosave: None,
+            reflow_column: 72,
+            theme: "dark".to_string(),
+            color_mode: None,
+            styles: Vec::new(),
+            keybindings: Profile::Default,
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration files that exist. Problems are collected as
+    /// messages instead of aborting, so one bad key does not discard the rest.
+    pub fn load() -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut errors = Vec::new();
+        let project = env::current_dir()
+            .ok()
+            .and_then(|dir| find_upwards(&dir, PROJECT_CONFIG));
+        for path in user_config_path().into_iter().chain(project) {
+            if path.is_file() {
+                config.load_file(&path, &mut errors);
+            }
+        }
+        (config, errors)
+    }
+
+    fn load_file(&mut self, path: &Path, errors: &mut Vec<String>) {
+        let table = match fs::read_to_string(path) {
+            Ok(contents) => contents.parse::<Table>(),
+            Err(error) => {
+                errors.push(format!("{}: {error}", path.display()));
+                return;
+            }
+        };
+        match table {
+            Ok(table) => {
+                for (key, value) in &table {
+                    if let Err(error) = self.set(key, value) {
+                        errors.push(format!("{}: `{key}` {error}", path.display()));
+                    }
+                }
+            }
+            Err(error) => errors.push(format!("{}: {}", path.display(), error.message())),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        match key {
+            "tab_width" => self.tab_width = positive(value)?,
+            "soft_wrap" => self.soft_wrap = boolean(value)?,
+            "line_numbers" => self.line_numbers = boolean(value)?,
+            "tab_bar" => self.tab_bar = boolean(value)?,
+            "quit_times" => {
+                self.quit_times = value
+                    .as_integer()
+                    .and_then(|times| u8::try_from(times).ok())
+                    .ok_or("must be an integer from 0 to 255")?
+            }
+            "message_timeout" => self.message_timeout = Duration::from_secs(seconds(value)?),
+            "autosave" => {
+                let interval = seconds(value)?;
+                self.autosave = (interval > 0).then(|| Duration::from_secs(interval));
+            }
+            "reflow_column" => self.reflow_column = positive(value)?,
+            "theme" => self.theme = value.as_str().ok_or("must be a theme name")?.to_string(),
+            "color_mode" => {
+                self.color_mode = ColorSupport::parse(value.as_str().unwrap_or_default())?
+            }
+            "styles" => {
+                let table = value.as_table().ok_or("must be a table")?;
+                for (name, value) in table {
+                    if !STYLE_NAMES.contains(&name.as_str()) {
+                        return Err(format!("has unknown style `{name}`"));
+                    }
+                    Style::parse(value)
+                        .map_err(|error| format!("has invalid `{name}`: {error}"))?;
+                    self.styles.push((name.clone(), value.clone()));
+                }
+            }
+            "keybindings" => self.keybindings = Profile::parse(value.as_str().unwrap_or_default())?,
+            "keys" => {
+                let table = value.as_table().ok_or("must be a table")?;
+                for (keys, action) in table {
+                    let binding = parse_binding(keys, action)
+                        .map_err(|error| format!("has invalid `{keys}`: {error}"))?;
+                    self.keys.push(binding);
+                }
+            }
+            _ => return Err("is not a known setting".to_string()),
+        }
+        Ok(())
+    }
+}
+
+/// Parses a `"Ctrl-K Ctrl-C" = "quit"` entry. The action `"none"` unbinds
+/// the keys.
+fn parse_binding(keys: &str, action: &Value) -> Result<(Vec<KeyChord>, Option<Action>), String> {
+    let keys = parse_sequence(keys)?;
+    let name = action.as_str().ok_or("action must be a string")?;
+    if name == "none" {
+        return Ok((keys, None));
+    }
+    let action = Action::from_name(name).ok_or_else(|| format!("unknown action `{name}`"))?;
+    Ok((keys, Some(action)))
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    value
+        .as_bool()
+        .ok_or_else(|| "must be true or false".to_string())
+}
+
+fn positive(value: &Value) -> Result<usize, String> {
+    value
+        .as_integer()
+        .filter(|&number| number > 0)
+        .and_then(|number| usize::try_from(number).ok())
+        .ok_or_else(|| "must be a positive integer".to_string())
+}
+
+fn seconds(value: &Value) -> Result<u64, String> {
+    value
+        .as_integer()
+        .and_then(|number| u64::try_from(number).ok())
+        .ok_or_else(|| "must be a number of seconds".to_string())
+}
+
+/// The directory holding te's configuration files.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("te"))
+}
+
+/// The directory holding files te keeps between sessions, such as prompt
+/// histories.
+pub fn state_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+        })?;
+    Some(base.join("te"))
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+fn find_upwards(dir: &Path, name: &str) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
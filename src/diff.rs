@@ -0,0 +1,180 @@
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+/// Beyond this many line pairs the changed middle of the files is shown as
+/// removed and added wholesale rather than matched line by line.
+const MAX_CELLS: usize = 1 << 22;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Same,
+    Removed,
+    Added,
+}
+
+/// The lines of a unified diff from `old` to `new`, without file headers.
+/// Empty when they are the same.
+pub fn unified(old: &[&str], new: &[&str]) -> Vec<String> {
+    let ops = diff(old, new);
+    let changes: Vec<usize> = (0..ops.len())
+        .filter(|&index| ops[index].0 != Op::Same)
+        .collect();
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while start < changes.len() {
+        // Changes whose context overlaps go in the same hunk.
+        let mut end = start;
+        while end + 1 < changes.len() && changes[end + 1] - changes[end] <= 2 * CONTEXT + 1 {
+            end += 1;
+        }
+        let first = changes[start].saturating_sub(CONTEXT);
+        let last = (changes[end] + CONTEXT + 1).min(ops.len());
+        let hunk = &ops[first..last];
+        let count = |op: Op| hunk.iter().filter(|(other, ..)| *other == op).count();
+        let (old_count, new_count) = (
+            count(Op::Same) + count(Op::Removed),
+            count(Op::Same) + count(Op::Added),
+        );
+        // An empty range is numbered by the line before it, as diff does.
+        let number = |line: usize, count: usize| if count == 0 { line } else { line + 1 };
+        lines.push(format!(
+            "@@ -{},{old_count} +{},{new_count} @@",
+            number(ops[first].1, old_count),
+            number(ops[first].2, new_count),
+        ));
+        for &(op, old_index, new_index) in hunk {
+            lines.push(match op {
+                Op::Same => format!(" {}", old[old_index]),
+                Op::Removed => format!("-{}", old[old_index]),
+                Op::Added => format!("+{}", new[new_index]),
+            });
+        }
+        start = end + 1;
+    }
+    lines
+}
+
+/// The edit script turning `old` into `new`, as operations with the index
+/// each refers to in both, based on their longest common subsequence.
+fn diff(old: &[&str], new: &[&str]) -> Vec<(Op, usize, usize)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<(Op, usize, usize)> = (0..prefix).map(|i| (Op::Same, i, i)).collect();
+    let (mut i, mut j) = (0, 0);
+    if a.len().saturating_mul(b.len()) <= MAX_CELLS {
+        // lengths[i][j] is the length of the common subsequence of a[i..]
+        // and b[j..].
+        let width = b.len() + 1;
+        let mut lengths = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lengths[i * width + j] = if a[i] == b[j] {
+                    lengths[(i + 1) * width + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+                };
+            }
+        }
+        while i < a.len() && j < b.len() {
+            if a[i] == b[j] {
+                ops.push((Op::Same, prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+                ops.push((Op::Removed, prefix + i, prefix + j));
+                i += 1;
+            } else {
+                ops.push((Op::Added, prefix + i, prefix + j));
+                j += 1;
+            }
+        }
+    }
+    ops.extend((i..a.len()).map(|i| (Op::Removed, prefix + i, prefix + j)));
+    ops.extend((j..b.len()).map(|j| (Op::Added, prefix + a.len(), prefix + j)));
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+    ops.extend((0..suffix).map(|k| (Op::Same, old_end + k, new_end + k)));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified_same_is_empty() {
+        assert!(unified(&[], &[]).is_empty());
+        assert!(unified(&["a", "b"], &["a", "b"]).is_empty());
+    }
+
+    #[test]
+    fn unified_change_with_context() {
+        let old = ["1", "2", "3", "4", "5", "6", "7", "8", "9"];
+        let new = ["1", "2", "3", "4", "five", "6", "7", "8", "9"];
+        assert_eq!(
+            unified(&old, &new),
+            [
+                "@@ -2,7 +2,7 @@",
+                " 2",
+                " 3",
+                " 4",
+                "-5",
+                "+five",
+                " 6",
+                " 7",
+                " 8"
+            ]
+        );
+    }
+
+    #[test]
+    fn unified_insert_and_delete() {
+        assert_eq!(
+            unified(&["a", "c"], &["a", "b", "c"]),
+            ["@@ -1,2 +1,3 @@", " a", "+b", " c"]
+        );
+        assert_eq!(
+            unified(&["a", "b", "c"], &["a", "c"]),
+            ["@@ -1,3 +1,2 @@", " a", "-b", " c"]
+        );
+    }
+
+    #[test]
+    fn unified_empty_side() {
+        assert_eq!(unified(&[], &["a"]), ["@@ -0,0 +1,1 @@", "+a"]);
+        assert_eq!(unified(&["a", "b"], &[]), ["@@ -1,2 +0,0 @@", "-a", "-b"]);
+    }
+
+    #[test]
+    fn unified_splits_distant_changes() {
+        let old: Vec<String> = (1..=20).map(|n| n.to_string()).collect();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let mut new = old.clone();
+        new[1] = "x";
+        new[18] = "y";
+        let lines = unified(&old, &new);
+        let headers: Vec<&str> = lines
+            .iter()
+            .map(String::as_str)
+            .filter(|line| line.starts_with("@@"))
+            .collect();
+        assert_eq!(headers, ["@@ -1,5 +1,5 @@", "@@ -16,5 +16,5 @@"]);
+    }
+
+    #[test]
+    fn unified_joins_nearby_changes() {
+        let old = ["1", "2", "3", "4", "5", "6", "7", "8", "9", "10"];
+        let mut new = old;
+        new[1] = "x";
+        new[8] = "y";
+        let lines = unified(&old, &new);
+        assert_eq!(lines[0], "@@ -1,10 +1,10 @@");
+        assert_eq!(lines.len(), 13);
+    }
+}
@@ -1,15 +1,83 @@
 use std::{
     fs,
-    io::{Error, Write},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    path::Path,
+    time::SystemTime,
 };
 
+use unicode_segmentation::UnicodeSegmentation;
+
 use crate::{Position, Row};
 
+/// How much of the end of a followed file is read again to check that it is
+/// still the same file.
+const TAIL_LEN: u64 = 1024;
+
+const COMMENT_PREFIXES: [&str; 5] = ["///", "//!", "//", "#", ">"];
+
+/// A single undoable change: `removed` rows starting at `at` were replaced
+/// by `inserted`.
+struct Edit {
+    at: usize,
+    removed: Vec<Row>,
+    inserted: Vec<Row>,
+    position: Position,
+    mergeable: bool,
+}
+
+/// What the file looked like when it was last read or written, to notice
+/// changes made by other programs.
+#[derive(Clone, Copy, PartialEq)]
+struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    /// Unknown once the file has been read in pieces by `read_appended`.
+    hash: Option<u64>,
+    inode: u64,
+    /// Checksum of the last `TAIL_LEN` bytes, to tell a file rewritten in
+    /// place from one that only grew.
+    tail: u64,
+    /// Whether the last line is complete, so appended text starts a new row.
+    ends_in_newline: bool,
+}
+
+impl DiskState {
+    fn of(metadata: &fs::Metadata, contents: &[u8]) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash: Some(checksum(contents)),
+            inode: metadata.ino(),
+            tail: checksum(tail(contents)),
+            ends_in_newline: contents.is_empty() || contents.ends_with(b"\n"),
+        }
+    }
+
+    /// The state of the file called `name` now.
+    fn read(name: &str) -> Result<Self, Error> {
+        let contents = fs::read(name)?;
+        Ok(Self::of(&fs::metadata(name)?, &contents))
+    }
+}
+
 #[derive(Default)]
 pub struct Document {
     rows: Vec<Row>,
     pub file_name: Option<String>,
     dirty: bool,
+    /// Set for generated text such as directory listings; edits are ignored
+    /// and saving fails.
+    read_only: bool,
+    /// Unset until the document has been read from or written to its file.
+    disk: Option<DiskState>,
+    /// Whether lines end in CRLF rather than LF, as read from the file.
+    crlf: bool,
+    /// Whether the text read lacked a line break after its last line.
+    no_final_newline: bool,
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
 }
 
 impl Document {
@@ -19,11 +87,191 @@ impl Document {
         for value in contents.lines() {
             rows.push(Row::from(value));
         }
-        Ok(Self {
-            rows: rows,
+        let mut document = Self {
+            rows,
             file_name: Some(filename.to_string()),
-            dirty: false,
-        })
+            disk: Some(DiskState::of(&fs::metadata(filename)?, contents.as_bytes())),
+            ..Self::default()
+        };
+        document.keep_line_endings(&contents);
+        Ok(document)
+    }
+
+    /// Whether another program changed the file since it was last read or
+    /// written, or created it if it was new. A file that is gone does not
+    /// count.
+    pub fn changed_on_disk(&mut self) -> bool {
+        let Some(name) = self.file_name.as_ref().filter(|_| !self.read_only) else {
+            return false;
+        };
+        let Some(disk) = self.disk else {
+            return Path::new(name).exists();
+        };
+        let Ok(metadata) = fs::metadata(name) else {
+            return false;
+        };
+        if metadata.modified().ok() == disk.modified && metadata.len() == disk.len {
+            return false;
+        }
+        let Ok(current) = DiskState::read(name) else {
+            return false;
+        };
+        // Only the timestamp changed, as when a formatter leaves the file
+        // as it was.
+        if disk.hash.is_some() && current.hash == disk.hash {
+            self.disk = Some(current);
+            return false;
+        }
+        true
+    }
+
+    /// Adds the text appended to the file since it was last read as new
+    /// rows, without rereading the rest, and returns how many rows were
+    /// added. None means the file changed in another way, such as being
+    /// truncated, replaced or created, and has to be reloaded. The new text
+    /// is one undo step, and leaves the document as modified as it was.
+    pub fn read_appended(&mut self) -> Result<Option<usize>, Error> {
+        let Some(name) = &self.file_name else {
+            return Ok(Some(0));
+        };
+        let Some(disk) = self.disk else {
+            return Ok(if Path::new(name).exists() {
+                None
+            } else {
+                Some(0)
+            });
+        };
+        let metadata = fs::metadata(name)?;
+        if metadata.len() < disk.len || metadata.ino() != disk.inode {
+            return Ok(None);
+        }
+        if metadata.len() == disk.len && metadata.modified().ok() == disk.modified {
+            return Ok(Some(0));
+        }
+        let start = disk.len.saturating_sub(TAIL_LEN);
+        let mut file = fs::File::open(name)?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let seen = ((disk.len - start) as usize).min(bytes.len());
+        if checksum(&bytes[..seen]) != disk.tail {
+            return Ok(None);
+        }
+        let appended = &bytes[seen..];
+        // A character cut off at the end is still being written and is left
+        // for the next call.
+        let text = match std::str::from_utf8(appended) {
+            Ok(text) => text,
+            Err(error) if error.error_len().is_none() => {
+                std::str::from_utf8(&appended[..error.valid_up_to()]).unwrap_or_default()
+            }
+            Err(error) => return Err(Error::new(ErrorKind::InvalidData, error)),
+        };
+        let mut lines: Vec<&str> = text.split('\n').collect();
+        let ends_in_newline = lines.last() == Some(&"");
+        if ends_in_newline {
+            lines.pop();
+        }
+        self.disk = Some(DiskState {
+            modified: metadata.modified().ok(),
+            len: disk.len + text.len() as u64,
+            hash: None,
+            inode: disk.inode,
+            tail: checksum(tail(&bytes[..seen + text.len()])),
+            ends_in_newline: if text.is_empty() {
+                disk.ends_in_newline
+            } else {
+                ends_in_newline
+            },
+        });
+        if text.is_empty() {
+            return Ok(Some(0));
+        }
+        self.no_final_newline = !ends_in_newline;
+
+        let mut rows: Vec<Row> = lines
+            .iter()
+            .map(|line| Row::from(line.strip_suffix('\r').unwrap_or(line)))
+            .collect();
+        let dirty = self.dirty;
+        let mut at = Position {
+            x: 0,
+            y: self.rows.len(),
+        };
+        let mut count = 0;
+        if let Some(last) = self.rows.last().filter(|_| !disk.ends_in_newline) {
+            // The first line completes the last row.
+            let mut joined = last.clone();
+            joined.append(&rows[0]);
+            rows[0] = joined;
+            at.y -= 1;
+            count = 1;
+        }
+        let added = rows.len() - count;
+        self.replace_rows(&at, count, rows, false);
+        self.dirty = dirty;
+        Ok(Some(added))
+    }
+
+    /// Stops reporting the current change on disk, so the document's text
+    /// wins when it is saved.
+    pub fn keep_changes(&mut self) {
+        if let Some(name) = &self.file_name {
+            self.disk = DiskState::read(name).ok().or(self.disk);
+        }
+    }
+
+    /// Replaces the text with what is in the file now, as a single undo
+    /// step.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let Some(name) = &self.file_name else {
+            return Ok(());
+        };
+        let contents = fs::read_to_string(name)?;
+        let disk = DiskState::of(&fs::metadata(name)?, contents.as_bytes());
+        let rows = contents.lines().map(Row::from).collect();
+        self.replace_rows(&Position::default(), self.rows.len(), rows, false);
+        self.keep_line_endings(&contents);
+        self.disk = Some(disk);
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Makes `to_bytes` end lines the way `contents` does, and leave out the
+    /// final line break if `contents` has none.
+    fn keep_line_endings(&mut self, contents: &str) {
+        self.crlf = contents
+            .find('\n')
+            .is_some_and(|end| contents[..end].ends_with('\r'));
+        self.no_final_newline = !contents.is_empty() && !contents.ends_with('\n');
+    }
+
+    /// An empty document to be saved as `filename`.
+    pub fn named(filename: &str) -> Self {
+        Self {
+            file_name: Some(filename.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// An unnamed document holding `text`, as read from standard input.
+    pub fn from_text(text: &str) -> Self {
+        let mut document = Self {
+            rows: text.lines().map(Row::from).collect(),
+            ..Self::default()
+        };
+        document.keep_line_endings(text);
+        document
+    }
+
+    /// A read-only document with the given lines, shown under `name`.
+    pub fn read_only(name: &str, lines: &[String]) -> Self {
+        Self {
+            rows: lines.iter().map(|line| Row::from(line.as_str())).collect(),
+            file_name: Some(name.to_string()),
+            read_only: true,
+            ..Self::default()
+        }
     }
 
     pub fn row(&self, index: usize) -> Option<&Row> {
@@ -38,30 +286,35 @@ impl Document {
         self.rows.len()
     }
 
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     pub fn insert_newline(&mut self, at: &Position) {
         if at.y > self.rows.len() {
             return;
         }
         if at.y == self.rows.len() {
-            self.rows.push(Row::default());
+            self.replace_rows(at, 0, vec![Row::default()], false);
             return;
         }
-        let new_row = self.rows[at.y].split(at.x);
-        self.rows.insert(at.y + 1, new_row);
+        let mut row = self.rows[at.y].clone();
+        let new_row = row.split(at.x);
+        self.replace_rows(at, 1, vec![row, new_row], false);
     }
 
     pub fn insert(&mut self, at: &Position, c: char) {
         if at.y > self.rows.len() {
             return;
         }
-        self.dirty = true;
         if at.y == self.rows.len() {
             let mut row = Row::default();
             row.insert(0, c);
-            self.rows.push(row);
+            self.replace_rows(at, 0, vec![row], false);
         } else {
-            let row = &mut self.rows[at.y];
+            let mut row = self.rows[at.y].clone();
             row.insert(at.x, c);
+            self.replace_rows(at, 1, vec![row], true);
         }
     }
 
@@ -70,30 +323,370 @@ impl Document {
         if at.y >= len {
             return;
         }
-        self.dirty = true;
-        if at.x == self.rows[at.y].len() && at.y + 1 < len {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = &mut self.rows[at.y];
-            row.append(&next_row);
+        let mut row = self.rows[at.y].clone();
+        if at.x >= row.len() && at.y + 1 >= len {
+            return;
+        }
+        if at.x == row.len() && at.y + 1 < len {
+            row.append(&self.rows[at.y + 1]);
+            self.replace_rows(at, 2, vec![row], false);
         } else {
-            let row = &mut self.rows[at.y];
             row.delete(at.x);
+            self.replace_rows(at, 1, vec![row], true);
         }
     }
 
-    pub fn save(&mut self) -> Result<(), Error> {
-        if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            for row in &self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+    /// The text from `start` up to `end`, with rows joined by newlines.
+    pub fn text(&self, start: &Position, end: &Position) -> String {
+        let (start, end) = (self.clamp(start), self.clamp(end));
+        let Some(first) = self.rows.get(start.y) else {
+            return String::new();
+        };
+        if start.y == end.y {
+            return first.slice(start.x, end.x).to_string();
+        }
+        let mut text = first.slice(start.x, first.len()).to_string();
+        for row in &self.rows[start.y + 1..end.y] {
+            text.push('\n');
+            text.push_str(row.as_str());
+        }
+        text.push('\n');
+        text.push_str(self.rows[end.y].slice(0, end.x));
+        text
+    }
+
+    /// Replaces the text from `start` up to `end` with `text` as a single
+    /// undo step, and returns the position just after the new text.
+    pub fn replace(&mut self, start: &Position, end: &Position, text: &str) -> Position {
+        let (start, end) = (self.clamp(start), self.clamp(end));
+        let (before, after, count) = match self.rows.get(start.y) {
+            Some(row) => (
+                row.slice(0, start.x),
+                self.rows[end.y].slice(end.x, self.rows[end.y].len()),
+                end.y - start.y + 1,
+            ),
+            None => ("", "", 0),
+        };
+        let rows: Vec<Row> = format!("{before}{text}{after}")
+            .split('\n')
+            .map(Row::from)
+            .collect();
+        let last_line = format!("{before}{text}");
+        let last_line = last_line.rsplit('\n').next().unwrap_or_default();
+        let position = Position {
+            x: last_line.graphemes(true).count(),
+            y: start.y + text.matches('\n').count(),
+        };
+        self.replace_rows(&start, count, rows, false);
+        position
+    }
+
+    /// Moves `position` inside the document; positions past the end become
+    /// the end of the last row.
+    fn clamp(&self, position: &Position) -> Position {
+        match self.rows.len() {
+            0 => Position::default(),
+            len if position.y >= len => Position {
+                x: self.rows[len - 1].len(),
+                y: len - 1,
+            },
+            _ => Position {
+                x: position.x.min(self.rows[position.y].len()),
+                y: position.y,
+            },
+        }
+    }
+
+    /// Rewraps the paragraph containing row `y` so no line exceeds `column`
+    /// graphemes, keeping the indentation and comment prefix of its first
+    /// line. Returns the end of the reflowed paragraph.
+    pub fn reflow(&mut self, y: usize, column: usize) -> Option<Position> {
+        let (prefix, body) = split_prefix(self.rows.get(y)?.as_str());
+        if body.is_empty() {
+            return None;
+        }
+        let prefix = prefix.trim_end().to_string();
+        let mut start = y;
+        while start > 0 && in_paragraph(&self.rows[start - 1], &prefix) {
+            start -= 1;
+        }
+        self.reflow_rows(start, y, column)
+    }
+
+    /// Rewraps every paragraph that starts in rows `first..=last_row`, as one
+    /// undo step. Returns the end of the last one, or `None` if the rows
+    /// hold no text.
+    pub fn reflow_rows(
+        &mut self,
+        first: usize,
+        last_row: usize,
+        column: usize,
+    ) -> Option<Position> {
+        let mut rows = Vec::new();
+        let mut last = None;
+        let mut end = first;
+        while end <= last_row && end < self.rows.len() {
+            let (prefix, body) = split_prefix(self.rows[end].as_str());
+            if body.is_empty() {
+                rows.push(self.rows[end].clone());
+                end += 1;
+                continue;
+            }
+            let prefix = prefix.to_string();
+            let start = end;
+            end += 1;
+            while end < self.rows.len() && in_paragraph(&self.rows[end], prefix.trim_end()) {
+                end += 1;
+            }
+            rows.extend(wrap(&self.rows[start..end], &prefix, column));
+            last = Some(Position {
+                x: rows[rows.len() - 1].len(),
+                y: first + rows.len() - 1,
+            });
+        }
+        let last = last?;
+        let unchanged = rows.len() == end - first
+            && rows
+                .iter()
+                .zip(&self.rows[first..end])
+                .all(|(new, old)| new.as_str() == old.as_str());
+        if !unchanged {
+            self.replace_rows(&Position { x: 0, y: first }, end - first, rows, false);
+        }
+        Some(last)
+    }
+
+    /// Reverts the most recent edit and returns where it happened.
+    pub fn undo(&mut self) -> Option<Position> {
+        let edit = self.undo_stack.pop()?;
+        let position = edit.position;
+        let inverse = self.revert(edit);
+        self.redo_stack.push(inverse);
+        Some(position)
+    }
+
+    /// Reapplies the most recently undone edit and returns where it happened.
+    pub fn redo(&mut self) -> Option<Position> {
+        let edit = self.redo_stack.pop()?;
+        let position = edit.position;
+        let inverse = self.revert(edit);
+        self.undo_stack.push(inverse);
+        Some(position)
+    }
+
+    /// Replaces `count` rows starting at row `at.y` with `rows`, recording
+    /// the change for undo. Consecutive mergeable edits of the same row are
+    /// folded into one undo step.
+    fn replace_rows(&mut self, at: &Position, count: usize, rows: Vec<Row>, mergeable: bool) {
+        if self.read_only {
+            return;
+        }
+        self.dirty = true;
+        self.redo_stack.clear();
+        let removed: Vec<Row> = self.rows.splice(at.y..at.y + count, rows.clone()).collect();
+        if let Some(last) = self.undo_stack.last_mut() {
+            if mergeable && last.mergeable && last.at == at.y {
+                last.inserted = rows;
+                return;
             }
-            self.dirty = false;
         }
+        self.undo_stack.push(Edit {
+            at: at.y,
+            removed,
+            inserted: rows,
+            position: Position { x: at.x, y: at.y },
+            mergeable,
+        });
+    }
+
+    /// Reverts `edit` and returns the edit that reapplies it.
+    fn revert(&mut self, edit: Edit) -> Edit {
+        self.dirty = true;
+        let end = edit.at + edit.inserted.len();
+        self.rows.splice(edit.at..end, edit.removed.clone());
+        Edit {
+            at: edit.at,
+            removed: edit.inserted,
+            inserted: edit.removed,
+            position: edit.position,
+            mergeable: false,
+        }
+    }
+
+    pub fn save(&mut self) -> Result<(), Error> {
+        match self.file_name.clone() {
+            Some(name) => self.save_to(&name),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the document to the file `name`, which becomes its file name
+    /// once written.
+    pub fn save_to(&mut self, name: &str) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "the buffer is read-only",
+            ));
+        }
+        let contents = self.to_bytes();
+        let mut file = fs::File::create(name)?;
+        file.write_all(&contents)?;
+        self.file_name = Some(name.to_string());
+        self.disk = Some(DiskState::of(&file.metadata()?, &contents));
+        self.dirty = false;
         Ok(())
     }
 
+    /// The text as saved, with a line break after every row, in the style
+    /// of the file it was read from.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let newline: &[u8] = if self.crlf { b"\r\n" } else { b"\n" };
+        let mut contents = Vec::new();
+        for (index, row) in self.rows.iter().enumerate() {
+            contents.extend_from_slice(row.as_bytes());
+            if !(self.no_final_newline && index + 1 == self.rows.len()) {
+                contents.extend_from_slice(newline);
+            }
+        }
+        contents
+    }
+
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
 }
+
+/// A hash of `bytes`, to tell whether file contents changed.
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The last `TAIL_LEN` bytes of `contents`.
+fn tail(contents: &[u8]) -> &[u8] {
+    &contents[contents.len().saturating_sub(TAIL_LEN as usize)..]
+}
+
+/// Whether `row` continues a paragraph whose lines start with `prefix`.
+fn in_paragraph(row: &Row, prefix: &str) -> bool {
+    let (row_prefix, row_body) = split_prefix(row.as_str());
+    !row_body.is_empty() && row_prefix.trim_end() == prefix
+}
+
+/// The words of `rows` filled into lines of at most `column` graphemes,
+/// each starting with `prefix`.
+fn wrap(rows: &[Row], prefix: &str, column: usize) -> Vec<Row> {
+    let width = column.saturating_sub(prefix.graphemes(true).count());
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let mut line_len = 0;
+    for row in rows {
+        for word in split_prefix(row.as_str()).1.split_whitespace() {
+            let word_len = word.graphemes(true).count();
+            if line_len > 0 && line_len + 1 + word_len > width {
+                lines.push(line);
+                line = String::new();
+                line_len = 0;
+            }
+            if line_len > 0 {
+                line.push(' ');
+                line_len += 1;
+            }
+            line.push_str(word);
+            line_len += word_len;
+        }
+    }
+    lines.push(line);
+    lines
+        .iter()
+        .map(|line| Row::from(format!("{prefix}{line}").as_str()))
+        .collect()
+}
+
+/// Splits a line into its indentation and comment prefix and the text after
+/// it.
+fn split_prefix(line: &str) -> (&str, &str) {
+    let indent = line.len() - line.trim_start().len();
+    let rest = &line[indent..];
+    let marker = COMMENT_PREFIXES
+        .iter()
+        .find(|marker| rest.starts_with(*marker))
+        .map_or(0, |marker| marker.len());
+    let body = rest[marker..].trim_start();
+    line.split_at(line.len() - body.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(document: &Document) -> Vec<&str> {
+        (0..document.len())
+            .filter_map(|y| document.row(y))
+            .map(Row::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn reflow_fills_lines_to_the_column() {
+        let mut document = Document::from_text("one two\nthree four five six\n");
+        let end = document.reflow(1, 10).unwrap();
+        assert_eq!(lines(&document), ["one two", "three four", "five six"]);
+        assert_eq!((end.x, end.y), (8, 2));
+        assert!(document.is_dirty());
+    }
+
+    #[test]
+    fn reflow_keeps_comment_prefix_and_indent() {
+        let mut document = Document::from_text("    // alpha beta\n    // gamma delta epsilon\n");
+        document.reflow(0, 20).unwrap();
+        assert_eq!(
+            lines(&document),
+            ["    // alpha beta", "    // gamma delta", "    // epsilon"]
+        );
+    }
+
+    #[test]
+    fn reflow_stops_at_blank_lines_and_other_prefixes() {
+        let mut document = Document::from_text("a b\nc d\n\n# e f\n# g\nh\n");
+        document.reflow_rows(0, 5, 80).unwrap();
+        assert_eq!(lines(&document), ["a b c d", "", "# e f g", "h"]);
+    }
+
+    #[test]
+    fn reflow_rows_only_touches_paragraphs_starting_in_range() {
+        let mut document = Document::from_text("a\nb\n\nc\nd\n");
+        document.reflow_rows(0, 1, 80).unwrap();
+        assert_eq!(lines(&document), ["a b", "", "c", "d"]);
+    }
+
+    #[test]
+    fn reflow_is_one_undo_step() {
+        let mut document = Document::from_text("a b c d\ne f\n\ng h i\n");
+        document.reflow_rows(0, 3, 3).unwrap();
+        assert_eq!(lines(&document), ["a b", "c d", "e f", "", "g h", "i"]);
+        document.undo().unwrap();
+        assert_eq!(lines(&document), ["a b c d", "e f", "", "g h i"]);
+        assert!(document.undo().is_none());
+    }
+
+    #[test]
+    fn reflow_leaves_wrapped_text_alone() {
+        let mut document = Document::from_text("// a b\n// c\n");
+        let end = document.reflow(1, 6).unwrap();
+        assert_eq!((end.x, end.y), (4, 1));
+        assert!(!document.is_dirty());
+        assert!(document.undo().is_none());
+    }
+
+    #[test]
+    fn reflow_needs_text() {
+        let mut document = Document::from_text("\n  \n//\n");
+        assert!(document.reflow(0, 80).is_none());
+        assert!(document.reflow_rows(0, 2, 80).is_none());
+        assert!(document.reflow(9, 80).is_none());
+        assert!(!document.is_dirty());
+    }
+}
@@ -1,41 +1,69 @@
 use std::{
     env,
-    io::Error,
+    fs::{self, File},
+    io::{self, Error, ErrorKind, IsTerminal, Read, Write},
+    path::Path,
+    process,
     time::{Duration, Instant},
 };
 
-use crossterm::{
-    event::{KeyCode, KeyEventKind, KeyModifiers},
-    style::Color,
-    terminal,
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
 };
 
-use crate::{Document, Row, Terminal};
+use unicode_segmentation::UnicodeSegmentation;
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-const QUIT_TIMES: u8 = 1;
-const STATUS_BG_COLOR: Color = Color::Rgb {
-    r: 40,
-    g: 39,
-    b: 38,
-};
-const STATUS_FG_COLOR: Color = Color::Rgb {
-    r: 135,
-    g: 133,
-    b: 128,
-};
-const MESSAGE_BG_COLOR: Color = Color::Rgb {
-    r: 64,
-    g: 62,
-    b: 60,
+use crate::{
+    color::ColorSupport,
+    config::{Config, SETTINGS},
+    diff,
+    explorer::Listing,
+    finder::{self, FileIndex},
+    fuzzy::{self, PathRanking},
+    input::{Completer, Completion, History, Input},
+    keymap::{format_sequence, Action, KeyChord, Keymap, Lookup, Profile},
+    kill_ring::KillRing,
+    layout::{Direction, Layout, Rect, Split},
+    path,
+    search::{self, Hit, ProjectSearch},
+    theme::Theme,
+    vim::{self, Command, Insert, Mode, Operator, Parse, Register, Span, Vim},
+    Document, Row, Terminal,
 };
 
-#[derive(Default)]
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const WINDOW_RESIZE_STEP: i16 = 5;
+const MAX_TAB_NAME: usize = 20;
+const LISTING_HELP: &str =
+    "Enter = open | - = parent | n = new file | + = new directory | r = rename | d = delete";
+/// Vim keeps its letters in Normal mode, so listings offer less there.
+const VIM_LISTING_HELP: &str =
+    "Enter = open | Backspace = parent | Delete = delete | n, +, r and d work in Insert mode";
+/// How many of the best matches the file finder lists.
+const MAX_FINDER_RESULTS: usize = 1000;
+/// How often the file finder and project search redraw while they are
+/// still going.
+const FINDER_REFRESH: Duration = Duration::from_millis(50);
+/// How often followed files are checked for appended text.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+/// How often open files are checked for changes made by other programs.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Rows below the windows taken by the status and message bars.
+const STATUS_ROWS: usize = 2;
+
+#[derive(Default, Clone, Copy)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+/// A tab in the tab bar; scroll markers have no buffer.
+struct Tab {
+    buffer: Option<usize>,
+    x: usize,
+    label: String,
+}
+
 struct StatusMessage {
     text: String,
     time: Instant,
@@ -50,46 +78,311 @@ impl StatusMessage {
     }
 }
 
-pub struct Editor {
-    should_quit: bool,
-    terminal: Terminal,
+/// Cursor and scroll state of a buffer.
+#[derive(Default, Clone, Copy)]
+struct View {
     cursor_position: Position,
     offset: Position,
+    wrap_offset: usize,
+    desired_column: Option<usize>,
+    /// The other end of the region, while one is active.
+    mark: Option<Position>,
+}
+
+/// A pane showing a buffer. The focused window's state lives in `Editor`
+/// itself while it has focus.
+struct Window {
+    buffer: usize,
+    view: View,
+}
+
+struct Buffer {
     document: Document,
+    view: View,
+    /// The directory shown, for buffers listing one.
+    listing: Option<Listing>,
+    /// The search shown, for project search results.
+    search: Option<ProjectSearch>,
+    /// Whether text appended to the file is added as it arrives.
+    follow: bool,
+    /// Whether this buffer holds the text read from standard input.
+    piped: bool,
+}
+
+impl Buffer {
+    fn from(document: Document) -> Self {
+        Self {
+            document,
+            view: View::default(),
+            listing: None,
+            search: None,
+            follow: false,
+            piped: false,
+        }
+    }
+
+    fn from_listing(listing: Listing) -> Self {
+        Self {
+            document: Document::read_only(&listing.name(), &listing.lines()),
+            view: View::default(),
+            listing: Some(listing),
+            search: None,
+            follow: false,
+            piped: false,
+        }
+    }
+
+    fn from_search(search: ProjectSearch) -> Self {
+        Self {
+            document: Document::read_only(&search.name(), &search.lines()),
+            view: View::default(),
+            listing: None,
+            search: Some(search),
+            follow: false,
+            piped: false,
+        }
+    }
+
+    /// Adds the results found since the last call to a search buffer.
+    /// Returns whether there were any, or the search finished.
+    fn poll_search(&mut self) -> bool {
+        let Some(search) = &mut self.search else {
+            return false;
+        };
+        let was_done = search.is_done();
+        if !search.poll() && search.is_done() == was_done {
+            return false;
+        }
+        self.refresh_search();
+        true
+    }
+
+    /// Rebuilds the text of a search buffer after its results changed.
+    fn refresh_search(&mut self) {
+        if let Some(search) = &self.search {
+            self.document = Document::read_only(&search.name(), &search.lines());
+        }
+    }
+}
+
+pub struct Editor {
+    should_quit: bool,
+    terminal: Terminal,
+    view: View,
+    buffers: Vec<Buffer>,
+    current_buffer: usize,
+    windows: Vec<Window>,
+    current_window: usize,
+    layout: Layout,
     status_message: StatusMessage,
     quit_times: u8,
+    config: Config,
+    keymap: Keymap,
+    /// Keys typed so far of a multi-key sequence.
+    pending_keys: Vec<KeyChord>,
+    theme: Theme,
+    /// Vim keybindings state, when they are enabled.
+    vim: Option<Vim>,
+    kill_ring: KillRing,
+    /// The action run by the previous key, so kills can append and yanks
+    /// can be cycled.
+    last_action: Option<Action>,
+    /// Where the text inserted by the last yank starts.
+    yank_start: Position,
+    /// The message bar column of the cursor while a prompt is open.
+    prompt_cursor: Option<usize>,
+    /// When the last key or mouse event came in, for autosave.
+    last_event: Instant,
+    last_disk_check: Instant,
+    /// Where the piped buffer is written on quit, with `--pipe`.
+    pipe_output: Option<File>,
+}
+
+/// The command line.
+#[derive(Default)]
+struct Args {
+    /// Files to open, with the position to start at in each.
+    files: Vec<(String, Option<Position>)>,
+    profile: Option<Result<Profile, String>>,
+    follow: bool,
+    /// Whether to read a buffer from standard input, with `-`.
+    stdin: bool,
+    pipe: bool,
+}
+
+impl Args {
+    /// Parses the arguments after the program name. `+N` applies to the
+    /// file after it, and `file:line[:col]` to that file.
+    fn parse(args: impl IntoIterator<Item = String>) -> Self {
+        let mut parsed = Self::default();
+        let mut target = None;
+        for arg in args {
+            if arg == "--follow" {
+                parsed.follow = true;
+            } else if arg == "-" {
+                parsed.stdin = true;
+            } else if arg == "--pipe" {
+                parsed.pipe = true;
+            } else if let Some(name) = arg.strip_prefix("--keybindings=") {
+                parsed.profile =
+                    Some(Profile::parse(name).map_err(|error| format!("--keybindings {error}")));
+            } else if let Some(line) = arg.strip_prefix('+').and_then(|line| line.parse().ok()) {
+                target = Some(Position {
+                    x: 0,
+                    y: usize::saturating_sub(line, 1),
+                });
+            } else {
+                let (name, position) = split_file_position(&arg);
+                parsed
+                    .files
+                    .push((name.to_string(), position.or(target.take())));
+            }
+        }
+        parsed
+    }
 }
 
 impl Editor {
     pub fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
-        let mut initial_status = String::from("HELP: Ctrl-S = save | Ctrl-Q = quit");
-        let document = if let Some(file_name) = args.get(1) {
-            let doc = Document::open(file_name);
-            if let Ok(doc) = doc {
-                doc
-            } else {
-                initial_status = format!("ERR: Could not open file: {file_name}");
-                Document::default()
+        let Args {
+            files,
+            profile,
+            follow,
+            stdin,
+            pipe,
+        } = Args::parse(env::args().skip(1));
+        let (mut config, mut errors) = Config::load();
+        match profile {
+            Some(Ok(profile)) => config.keybindings = profile,
+            Some(Err(error)) => errors.push(error),
+            None => (),
+        }
+        let color_support = config.color_mode.unwrap_or_else(ColorSupport::detect);
+        // Without colors, only the terminal theme's attributes tell styles
+        // apart.
+        let theme_name = match color_support {
+            ColorSupport::None => "terminal",
+            _ => &config.theme,
+        };
+        let theme = Theme::load(theme_name).unwrap_or_else(|error| {
+            errors.push(error);
+            Theme::default()
+        });
+        let mut keymap = Keymap::new(config.keybindings);
+        for (keys, action) in &config.keys {
+            keymap.bind(keys.clone(), *action);
+        }
+        let help = [
+            (Action::Save, "save"),
+            (Action::Quit, "quit"),
+            (Action::Undo, "undo"),
+            (Action::GoToLine, "go to"),
+            (Action::BufferPicker, "buffers"),
+            (Action::DescribeKey, "describe key"),
+        ]
+        .iter()
+        .map(|&(action, label)| format!("{} = {label}", key_name(&keymap, action)))
+        .collect::<Vec<_>>()
+        .join(" | ");
+        let mut buffers = Vec::new();
+        let mut targets = Vec::new();
+        if stdin || (pipe && !io::stdin().is_terminal()) {
+            let mut text = Vec::new();
+            if let Err(error) = io::stdin().read_to_end(&mut text) {
+                errors.push(format!("Could not read standard input: {error}"));
             }
-        } else {
-            Document::default()
+            let Ok(text) = String::from_utf8(text) else {
+                exit_with("standard input is not UTF-8 text");
+            };
+            let mut buffer = Buffer::from(Document::from_text(&text));
+            buffer.piped = true;
+            buffers.push(buffer);
+            targets.push(None);
+        } else if pipe {
+            let mut buffer = Buffer::from(Document::default());
+            buffer.piped = true;
+            buffers.push(buffer);
+            targets.push(None);
+        }
+        for (file_name, position) in files {
+            match open_buffer(&file_name) {
+                Ok(buffer) => {
+                    buffers.push(buffer);
+                    targets.push(position);
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+        if buffers.is_empty() {
+            buffers.push(Buffer::from(Document::default()));
+            targets.push(None);
+        }
+        let initial_status = match errors.first() {
+            Some(error) if errors.len() > 1 => {
+                format!("ERR: {error} (and {} more)", errors.len() - 1)
+            }
+            Some(error) => format!("ERR: {error}"),
+            None => format!("HELP: {help}"),
         };
 
-        Self {
+        let pipe_output = match pipe.then(Terminal::redirect_stdout).transpose() {
+            Ok(output) => output,
+            Err(error) => exit_with(&format!("--pipe needs a terminal to draw on: {error}")),
+        };
+        let mut terminal = Terminal::default().expect("Failed to initialize terminal");
+        terminal.set_color_support(color_support);
+        let mut editor = Self {
             should_quit: false,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
-            cursor_position: Position::default(),
-            offset: Position::default(),
-            document,
+            terminal,
+            view: View::default(),
+            buffers,
+            current_buffer: 0,
+            windows: vec![Window {
+                buffer: 0,
+                view: View::default(),
+            }],
+            current_window: 0,
+            layout: Layout::default(),
             status_message: StatusMessage::from(initial_status),
-            quit_times: QUIT_TIMES,
+            quit_times: config.quit_times,
+            config,
+            keymap,
+            pending_keys: Vec::new(),
+            theme: Theme::default(),
+            vim: None,
+            kill_ring: KillRing::default(),
+            last_action: None,
+            yank_start: Position::default(),
+            prompt_cursor: None,
+            last_event: Instant::now(),
+            last_disk_check: Instant::now(),
+            pipe_output,
+        };
+        editor.set_theme(theme);
+        if editor.config.keybindings == Profile::Vim {
+            editor.vim = Some(Vim::default());
+        }
+        for (index, target) in targets.into_iter().enumerate().rev() {
+            editor.switch_buffer(index);
+            if let Some(position) = target {
+                editor.go_to(position);
+            } else if follow && editor.can_follow() {
+                editor.toggle_follow();
+            }
         }
+        editor
     }
 
     pub fn run(&mut self) {
         loop {
-            if let Err(error) = self.refresh_screen() {
+            // The mouse is only used to click tabs, so leave it to the
+            // terminal otherwise.
+            let mouse_capture = self.tab_bar_visible();
+            if let Err(error) = self
+                .terminal
+                .set_mouse_capture(mouse_capture)
+                .and_then(|()| self.refresh_screen())
+            {
                 die(&error)
             }
             if self.should_quit {
@@ -99,7 +392,18 @@ impl Editor {
                 die(&error);
             }
         }
-        terminal::disable_raw_mode().unwrap();
+        Terminal::restore();
+        if let Some(output) = &mut self.pipe_output {
+            let text = self
+                .buffers
+                .iter()
+                .find(|buffer| buffer.piped)
+                .map(|buffer| buffer.document.to_bytes())
+                .unwrap_or_default();
+            if let Err(error) = output.write_all(&text).and_then(|()| output.flush()) {
+                eprintln!("te: could not write standard output: {error}");
+            }
+        }
     }
 
     fn refresh_screen(&self) -> Result<(), Error> {
@@ -110,129 +414,2177 @@ impl Editor {
             Terminal::clear_screen();
             println!("Goodbye and thanks for all the fish!\r");
         } else {
-            self.draw_rows();
-            self.draw_status_bar();
-            self.draw_message_bar();
+            if self.tab_bar_visible() {
+                self.draw_tab_bar();
+            }
+            self.draw_windows();
             Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
+                x: 0,
+                y: (self.terminal.size().height as usize).saturating_sub(STATUS_ROWS),
             });
+            self.draw_status_bar();
+            self.draw_message_bar();
+            match self.prompt_cursor {
+                Some(x) => Terminal::cursor_position(&Position {
+                    x: x.min((self.terminal.size().width as usize).saturating_sub(1)),
+                    y: (self.terminal.size().height as usize).saturating_sub(1),
+                }),
+                None => Terminal::cursor_position(&self.screen_cursor_position()),
+            }
         }
         Terminal::show_cursor();
         Terminal::flush()
     }
 
-    fn draw_welcome_message(&self) {
-        let mut welcome_message = format!("te editor -- version {VERSION}\r");
-        let width = self.terminal.size().width as usize;
+    fn welcome_message(width: usize) -> String {
+        let mut welcome_message = format!("te editor -- version {VERSION}");
         let len = welcome_message.len();
         let padding = width.saturating_sub(len) / 2;
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_message = format!("~{spaces}{welcome_message}");
         welcome_message.truncate(width);
-        println!("{welcome_message}\r");
+        welcome_message
+    }
+
+    fn screen_cursor_position(&self) -> Position {
+        let area = self.text_area();
+        let Position { x, y } = self.view.cursor_position;
+        let column = |at: usize| {
+            self.document()
+                .row(y)
+                .map_or(0, |row| row.column(at, self.config.tab_width))
+        };
+        if !self.config.soft_wrap {
+            return Position {
+                x: area.x + column(x).saturating_sub(column(self.view.offset.x)),
+                y: area.y + y.saturating_sub(self.view.offset.y),
+            };
+        }
+        let (cursor_y, cursor_segment) = self.cursor_line();
+        let mut line = (self.view.offset.y, self.view.wrap_offset);
+        let mut screen_y = 0;
+        while line < (cursor_y, cursor_segment) {
+            line = self.next_line(line);
+            screen_y += 1;
+        }
+        let (start, _) = self.segments(cursor_y)[cursor_segment];
+        Position {
+            x: area.x + column(x).saturating_sub(column(start)),
+            y: area.y + screen_y,
+        }
+    }
+
+    fn set_style(&self, name: &str) {
+        self.terminal.set_style(&self.theme.style(name));
     }
 
-    fn draw_rows(&self) {
-        let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
+    /// Makes `theme` current, with the `[styles]` overrides from the config
+    /// applied on top.
+    fn set_theme(&mut self, mut theme: Theme) {
+        for (name, value) in &self.config.styles {
+            // Overrides were validated when the config was loaded.
+            let _ = theme.set(name, value);
+        }
+        self.terminal.set_default_style(&theme.style("text"));
+        self.theme = theme;
+    }
 
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(terminal_row as usize))
+    /// Whether the terminal shows no colors, in which case the terminal theme
+    /// stays and the user is told why.
+    fn colors_off(&mut self) -> bool {
+        let off = self.terminal.color_support() == ColorSupport::None;
+        if off {
+            self.status_message = StatusMessage::from(
+                "Colors are off (NO_COLOR or color_mode = \"none\")".to_string(),
+            );
+        }
+        off
+    }
+
+    fn select_theme(&mut self) -> Result<(), Error> {
+        if self.colors_off() {
+            return Ok(());
+        }
+        let names = Theme::available();
+        let current = names
+            .iter()
+            .position(|name| *name == self.theme.name)
+            .unwrap_or(0);
+        if let Some(index) = self.pick("Theme: ", &names, current)? {
+            self.load_theme(&names[index]);
+        }
+        Ok(())
+    }
+
+    fn load_theme(&mut self, name: &str) {
+        if self.colors_off() {
+            return;
+        }
+        match Theme::load(name) {
+            Ok(theme) => {
+                self.set_theme(theme);
+                self.status_message = StatusMessage::from(format!("Theme: {name}"));
+            }
+            Err(error) => self.status_message = StatusMessage::from(format!("ERR: {error}")),
+        }
+    }
+
+    fn draw_windows(&self) {
+        let (windows, separators) = self.layout.arrange(self.editor_area());
+        for (index, rect) in windows {
+            self.draw_window(index, rect);
+        }
+        self.set_style("separator");
+        for separator in separators {
+            for y in separator.y..separator.y + separator.height {
+                Terminal::cursor_position(&Position { x: separator.x, y });
+                print!("\u{2502}");
+            }
+        }
+        self.terminal.reset_style();
+    }
+
+    fn draw_window(&self, index: usize, rect: Rect) {
+        let (buffer, view) = self.window_state(index);
+        let document = &self.buffers[buffer].document;
+        if self.windows.len() > 1 {
+            self.draw_window_title(index, rect);
+        }
+        let area = self.window_text_area(index, rect);
+        let gutter = self.gutter_width(document);
+        let tab_width = self.config.tab_width;
+        let selection = self.selection().filter(|_| index == self.current_window);
+        let mut line = (view.offset.y, view.wrap_offset);
+        for terminal_row in 0..area.height {
+            let (y, segment) = if self.config.soft_wrap {
+                line
+            } else {
+                (view.offset.y.saturating_add(terminal_row), 0)
+            };
+            let range = if let Some(row) = document.row(y) {
+                if self.config.soft_wrap {
+                    let segments = row.wrap(area.width, tab_width);
+                    line = next_line(document, area.width, tab_width, line);
+                    Some(segments[segment.min(segments.len() - 1)])
+                } else {
+                    Some((view.offset.x, row.len()))
+                }
+            } else {
+                None
+            };
+            if gutter > 0 {
+                Terminal::cursor_position(&Position {
+                    x: rect.x,
+                    y: area.y + terminal_row,
+                });
+                let number = if document.row(y).is_some() && segment == 0 {
+                    format!("{:>width$} ", y + 1, width = gutter - 1)
+                } else {
+                    String::new()
+                };
+                self.set_style("gutter");
+                print!("{}", pad(&number, gutter.min(rect.width)));
+                self.terminal.reset_style();
+            }
+            Terminal::cursor_position(&Position {
+                x: area.x,
+                y: area.y + terminal_row,
+            });
+            match (document.row(y), range) {
+                (Some(row), Some((start, end))) => {
+                    let selected = selection.and_then(|selection| selected_range(selection, y));
+                    self.draw_row(row, (start, end), area.width, selected);
+                }
+                _ if document.is_empty() && terminal_row == area.height / 3 => {
+                    print!("{}", pad(&Self::welcome_message(area.width), area.width));
+                }
+                _ => print!("{}", pad("~", area.width)),
+            }
+        }
+    }
+
+    /// Prints graphemes `start..end` of `row` padded to `width` columns,
+    /// highlighting the `selected` graphemes. A selection reaching past the
+    /// end of the row also highlights one cell after it.
+    fn draw_row(
+        &self,
+        row: &Row,
+        (start, end): (usize, usize),
+        width: usize,
+        selected: Option<(usize, usize)>,
+    ) {
+        let tab_width = self.config.tab_width;
+        let (from, to) = selected.unwrap_or((end, end));
+        let (from, to) = (from.clamp(start, end), to.clamp(start, end));
+        let mut remaining = width;
+        for (a, b, highlight) in [(start, from, false), (from, to, true), (to, end, false)] {
+            let text: String = row
+                .render(a, b, tab_width)
+                .graphemes(true)
+                .take(remaining)
+                .collect();
+            remaining -= text.graphemes(true).count();
+            if highlight {
+                self.set_style("selection");
+            }
+            print!("{text}");
+            if highlight {
+                self.terminal.reset_style();
+            }
+        }
+        let past_end = selected.is_some_and(|(_, to)| to > row.len()) && end == row.len();
+        if past_end && remaining > 0 {
+            self.set_style("selection");
+            print!(" ");
+            self.terminal.reset_style();
+            remaining -= 1;
+        }
+        print!("{}", " ".repeat(remaining));
+    }
+
+    fn draw_window_title(&self, index: usize, rect: Rect) {
+        let (buffer, _) = self.window_state(index);
+        let title = format!("\u{2500} {} ", self.buffer_name(buffer));
+        let fill = "\u{2500}".repeat(rect.width);
+        if index == self.current_window {
+            self.set_style("title.active");
+        } else {
+            self.set_style("title");
+        }
+        Terminal::cursor_position(&Position {
+            x: rect.x,
+            y: rect.y,
+        });
+        print!("{}", pad(&format!("{title}{fill}"), rect.width));
+        self.terminal.reset_style();
+    }
+
+    fn process_keypress(&mut self) -> Result<(), Error> {
+        let mut timeout = DISK_CHECK_INTERVAL.saturating_sub(self.last_disk_check.elapsed());
+        if let Some(interval) = self.config.autosave {
+            timeout = timeout.min(interval.saturating_sub(self.last_event.elapsed()));
+        }
+        if self.buffers.iter().any(|buffer| {
+            buffer
+                .search
+                .as_ref()
+                .is_some_and(|search| !search.is_done())
+        }) {
+            timeout = timeout.min(FINDER_REFRESH);
+        }
+        if self.buffers.iter().any(|buffer| buffer.follow) {
+            timeout = timeout.min(FOLLOW_INTERVAL);
+        }
+        let Some(event) = Terminal::poll_event(timeout)? else {
+            for buffer in &mut self.buffers {
+                buffer.poll_search();
+            }
+            self.follow_files();
+            if self.last_disk_check.elapsed() >= DISK_CHECK_INTERVAL {
+                self.check_files()?;
+            }
+            if self
+                .config
+                .autosave
+                .is_some_and(|interval| self.last_event.elapsed() >= interval)
+            {
+                self.autosave();
+                self.last_event = Instant::now();
+            }
+            return Ok(());
+        };
+        self.last_event = Instant::now();
+        let pressed_key = match event {
+            Event::Key(pressed_key) => pressed_key,
+            Event::Mouse(mouse_event) => {
+                self.process_mouse(mouse_event);
+                return Ok(());
+            }
+            Event::FocusGained => return self.check_files(),
+            _ => return Ok(()),
+        };
+        if pressed_key.kind == KeyEventKind::Release {
+            return Ok(());
+        }
+        self.process_key(pressed_key)
+    }
+
+    fn process_key(&mut self, pressed_key: KeyEvent) -> Result<(), Error> {
+        if self.process_listing_key(pressed_key)? || self.process_search_key(pressed_key) {
+            self.last_action = None;
+            return Ok(());
+        }
+        if self.process_vim_key(pressed_key)? {
+            self.last_action = None;
+            return Ok(());
+        }
+        self.pending_keys.push(KeyChord::from(pressed_key));
+        let action = match self.keymap.lookup(&self.pending_keys) {
+            Lookup::Action(action) => Some(action),
+            Lookup::Prefix => {
+                let keys = format_sequence(&self.pending_keys);
+                self.status_message = StatusMessage::from(format!("{keys} -"));
+                return Ok(());
+            }
+            Lookup::Unbound => None,
+        };
+        let keys = std::mem::take(&mut self.pending_keys);
+        if keys.len() > 1 {
+            // Clear the hint showing the prefix typed so far.
+            self.status_message = StatusMessage::from(String::new());
+        }
+        match (action, &keys[..]) {
+            (Some(action), _) => self.perform(action)?,
+            (None, [chord]) => {
+                if let Some(c) = chord.text() {
+                    self.insert_char(c);
+                }
+            }
+            (None, [.., last])
+                if matches!(self.keymap.lookup(&[*last]), Lookup::Action(Action::Cancel)) =>
             {
-                self.draw_row(row);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
+                self.status_message = StatusMessage::from("Cancelled".to_string());
+                return Ok(());
+            }
+            (None, _) => {
+                let keys = format_sequence(&keys);
+                self.status_message = StatusMessage::from(format!("{keys} is not bound"));
+                return Ok(());
+            }
+        }
+        if !action.is_some_and(Action::is_vertical) {
+            self.view.desired_column = None;
+        }
+        if !action.is_some_and(Action::keeps_mark) {
+            self.view.mark = None;
+        }
+        self.last_action = action;
+        if self
+            .vim
+            .as_ref()
+            .is_some_and(|vim| vim.mode != Mode::Insert)
+        {
+            self.clamp_vim_cursor();
+        }
+        self.scroll();
+        if action != Some(Action::Quit) && self.quit_times < self.config.quit_times {
+            self.quit_times = self.config.quit_times;
+            self.status_message = StatusMessage::from(String::new());
+        }
+        Ok(())
+    }
+
+    fn perform(&mut self, action: Action) -> Result<(), Error> {
+        match action {
+            Action::Quit => self.quit(),
+            Action::Open => {
+                let completer: Completer = &path::complete;
+                if let Some(name) = self.prompt_with("Open: ", Some("open"), Some(completer))? {
+                    self.open(&name);
+                }
+            }
+            Action::FindFile => self.find_file()?,
+            Action::ReplaceProject => self.replace_project()?,
+            Action::Reload => self.reload()?,
+            Action::ToggleFollow => self.toggle_follow(),
+            Action::SearchProject => {
+                if let Some(pattern) = self.prompt_with("Search project: ", Some("search"), None)? {
+                    self.search_project(&pattern);
+                }
+            }
+            Action::Save => self.save()?,
+            Action::SaveAs => {
+                let completer: Completer = &path::complete;
+                if let Some(name) =
+                    self.prompt_with("Save as: ", Some("saveas"), Some(completer))?
+                {
+                    self.save_as(&name)?;
+                }
+            }
+            Action::Undo => {
+                let position = self.document_mut().undo();
+                self.restore_cursor(position, "Nothing to undo");
+            }
+            Action::Redo => {
+                let position = self.document_mut().redo();
+                self.restore_cursor(position, "Nothing to redo");
+            }
+            Action::GoToLine => self.go_to_prompt()?,
+            Action::BufferPicker => self.buffer_picker()?,
+            Action::CloseBuffer => self.close_buffer()?,
+            Action::NextBuffer => self.cycle_buffer(1),
+            Action::PreviousBuffer => self.cycle_buffer(self.buffers.len() - 1),
+            Action::SplitHorizontal => self.split_window(Split::Horizontal),
+            Action::SplitVertical => self.split_window(Split::Vertical),
+            Action::CloseWindow => self.close_window(),
+            Action::FocusUp => self.move_focus(Direction::Up),
+            Action::FocusDown => self.move_focus(Direction::Down),
+            Action::FocusLeft => self.move_focus(Direction::Left),
+            Action::FocusRight => self.move_focus(Direction::Right),
+            Action::ShrinkHeight => {
+                self.layout
+                    .resize(self.current_window, Split::Horizontal, -WINDOW_RESIZE_STEP)
+            }
+            Action::GrowHeight => {
+                self.layout
+                    .resize(self.current_window, Split::Horizontal, WINDOW_RESIZE_STEP)
+            }
+            Action::ShrinkWidth => {
+                self.layout
+                    .resize(self.current_window, Split::Vertical, -WINDOW_RESIZE_STEP)
+            }
+            Action::GrowWidth => {
+                self.layout
+                    .resize(self.current_window, Split::Vertical, WINDOW_RESIZE_STEP)
+            }
+            Action::ToggleTabBar => self.config.tab_bar = !self.config.tab_bar,
+            Action::ToggleSoftWrap => self.toggle_soft_wrap(),
+            Action::ReflowParagraph => self.reflow_paragraph(),
+            Action::MoveUp => self.move_cursor(KeyCode::Up),
+            Action::MoveDown => self.move_cursor(KeyCode::Down),
+            Action::MoveLeft => self.move_cursor(KeyCode::Left),
+            Action::MoveRight => self.move_cursor(KeyCode::Right),
+            Action::MoveWordLeft => self.move_word_left(),
+            Action::MoveWordRight => self.move_word_right(),
+            Action::LineStart => self.move_cursor(KeyCode::Home),
+            Action::LineEnd => self.move_cursor(KeyCode::End),
+            Action::PageUp => self.move_cursor(KeyCode::PageUp),
+            Action::PageDown => self.move_cursor(KeyCode::PageDown),
+            Action::DocumentStart => self.view.cursor_position = Position::default(),
+            Action::DocumentEnd => {
+                self.view.cursor_position = Position {
+                    x: 0,
+                    y: self.document().len(),
+                }
+            }
+            Action::InsertNewline => {
+                let at = self.view.cursor_position;
+                self.document_mut().insert_newline(&at);
+                self.move_cursor(KeyCode::Down);
+                self.move_cursor(KeyCode::Home);
+            }
+            Action::DeleteBackward => {
+                if self.view.cursor_position.x > 0 || self.view.cursor_position.y > 0 {
+                    self.move_cursor(KeyCode::Left);
+                    let at = self.view.cursor_position;
+                    self.document_mut().delete(&at);
+                }
+            }
+            Action::DeleteForward => {
+                let at = self.view.cursor_position;
+                self.document_mut().delete(&at);
+            }
+            Action::DeleteWordLeft => self.delete_word_left(),
+            Action::DeleteWordRight => self.delete_word_right(),
+            Action::DescribeKey => self.describe_key()?,
+            Action::SelectTheme => self.select_theme()?,
+            Action::CommandPalette => self.command_palette()?,
+            Action::Set => {
+                if let Some(setting) = self.prompt_with("Set: ", Some("set"), None)? {
+                    self.set_setting(&setting);
+                }
+            }
+            Action::SetMark => {
+                self.view.mark = Some(self.view.cursor_position);
+                self.status_message = StatusMessage::from("Mark set".to_string());
+            }
+            Action::Cancel => (),
+            Action::KillLine => self.kill_line(),
+            Action::KillRegion => self.kill_region(true),
+            Action::CopyRegion => self.kill_region(false),
+            Action::Yank => self.yank(),
+            Action::YankPop => self.yank_pop(),
+        }
+        Ok(())
+    }
+
+    fn insert_char(&mut self, c: char) {
+        if self.document().is_read_only() {
+            return;
+        }
+        let at = self.view.cursor_position;
+        self.document_mut().insert(&at, c);
+        self.move_cursor(KeyCode::Right);
+    }
+
+    /// Adds `text` to the kill ring, appending to the last kill if the
+    /// previous action was one.
+    fn kill(&mut self, text: String) {
+        let append = self.last_action.is_some_and(Action::is_kill);
+        self.kill_ring.kill(text, append);
+    }
+
+    /// Cuts to the end of the line, or the line break when already there.
+    fn kill_line(&mut self) {
+        let start = self.view.cursor_position;
+        let Some(row) = self.document().row(start.y) else {
+            self.status_message = StatusMessage::from("End of buffer".to_string());
+            return;
+        };
+        let end = if start.x < row.len() {
+            Position {
+                x: row.len(),
+                y: start.y,
+            }
+        } else if start.y + 1 < self.document().len() {
+            Position {
+                x: 0,
+                y: start.y + 1,
+            }
+        } else {
+            self.status_message = StatusMessage::from("End of buffer".to_string());
+            return;
+        };
+        let text = self.document().text(&start, &end);
+        self.document_mut().replace(&start, &end, "");
+        self.kill(text);
+    }
+
+    /// Copies the region to the kill ring, and removes it if `cut`.
+    fn kill_region(&mut self, cut: bool) {
+        let Some(mark) = self.view.mark else {
+            self.status_message = StatusMessage::from("The mark is not set".to_string());
+            return;
+        };
+        let (start, end) = ordered(mark, self.view.cursor_position);
+        let text = self.document().text(&start, &end);
+        if cut && !text.is_empty() {
+            self.document_mut().replace(&start, &end, "");
+            self.view.cursor_position = start;
+        }
+        self.kill(text);
+    }
+
+    fn yank(&mut self) {
+        let Some(text) = self.kill_ring.yank().map(str::to_string) else {
+            self.status_message = StatusMessage::from("Kill ring is empty".to_string());
+            return;
+        };
+        let start = self.view.cursor_position;
+        self.yank_start = start;
+        self.view.cursor_position = self.document_mut().replace(&start, &start, &text);
+    }
+
+    /// Replaces the text inserted by the yank just before with an older kill.
+    fn yank_pop(&mut self) {
+        if !matches!(self.last_action, Some(Action::Yank | Action::YankPop)) {
+            self.status_message =
+                StatusMessage::from("Previous command was not a yank".to_string());
+            return;
+        }
+        let Some(text) = self.kill_ring.rotate().map(str::to_string) else {
+            return;
+        };
+        let (start, end) = (self.yank_start, self.view.cursor_position);
+        self.view.cursor_position = self.document_mut().replace(&start, &end, &text);
+    }
+
+    /// How many buffers have changes that would be lost on quitting. With
+    /// `--pipe` the piped buffer's text is written out on quit instead.
+    fn unsaved_buffers(&self) -> usize {
+        let piped = self.pipe_output.is_some();
+        self.buffers
+            .iter()
+            .filter(|b| b.document.is_dirty() && !(piped && b.piped))
+            .count()
+    }
+
+    /// Quits, unless buffers have unsaved changes and the quit action has
+    /// not been repeated `quit_times` more times.
+    fn quit(&mut self) {
+        let dirty = self.unsaved_buffers();
+        if self.quit_times > 0 && dirty > 0 {
+            let files = if dirty == 1 {
+                "1 file has".to_string()
             } else {
-                println!("~\r");
+                format!("{dirty} files have")
+            };
+            let keys = self.key_name(Action::Quit);
+            self.status_message = StatusMessage::from(format!(
+                "WARNING! {files} unsaved changes. Press {keys} {} more times to quit",
+                self.quit_times
+            ));
+            self.quit_times -= 1;
+            return;
+        }
+        self.should_quit = true
+    }
+
+    /// Reads a key sequence and shows the action bound to it.
+    fn describe_key(&mut self) -> Result<(), Error> {
+        let mut keys = Vec::new();
+        let action = loop {
+            let typed = format_sequence(&keys);
+            self.status_message = StatusMessage::from(format!("Describe key: {typed}"));
+            self.refresh_screen()?;
+            let pressed_key = Terminal::read_key()?;
+            if pressed_key.kind == KeyEventKind::Release {
+                continue;
+            }
+            keys.push(KeyChord::from(pressed_key));
+            match self.keymap.lookup(&keys) {
+                Lookup::Action(action) => break Some(action),
+                Lookup::Prefix => (),
+                Lookup::Unbound => break None,
+            }
+        };
+        let name = format_sequence(&keys);
+        let message = match (action, &keys[..]) {
+            (Some(action), _) => {
+                format!("{name} runs `{}`: {}", action.name(), action.description())
+            }
+            (None, [chord]) if chord.text().is_some() => format!("{name} inserts itself"),
+            (None, _) => format!("{name} is not bound"),
+        };
+        self.status_message = StatusMessage::from(message);
+        Ok(())
+    }
+
+    /// The first key sequence bound to `action`, or its name if it is unbound.
+    fn key_name(&self, action: Action) -> String {
+        key_name(&self.keymap, action)
+    }
+
+    /// Handles `key` with the Vim bindings. Returns false for keys that go
+    /// through the keymap instead: anything typed in insert mode, and Ctrl,
+    /// Alt and function keys outside a pending command.
+    fn process_vim_key(&mut self, key: KeyEvent) -> Result<bool, Error> {
+        let Some(vim) = &mut self.vim else {
+            return Ok(false);
+        };
+        let mut vertical = false;
+        if vim.mode == Mode::Insert {
+            vim.record(key);
+            if key.code != KeyCode::Esc {
+                return Ok(false);
+            }
+            vim.finish_change();
+            vim.mode = Mode::Normal;
+            let position = &mut self.view.cursor_position;
+            position.x = position.x.saturating_sub(1);
+        } else {
+            let is_chord = key
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+                && KeyChord::from(key).text().is_none()
+                && !matches!(key.code, KeyCode::Char('r' | '['));
+            if !vim.is_pending() && (is_chord || matches!(key.code, KeyCode::F(_))) {
+                return Ok(false);
+            }
+            match vim.parse(key) {
+                Parse::Pending => return Ok(true),
+                Parse::Invalid => (),
+                Parse::Done(command) => vertical = self.run_vim_command(command)?,
             }
+            if let Some(vim) = self.vim.as_mut().filter(|vim| vim.mode != Mode::Insert) {
+                vim.finish_change();
+            }
+        }
+        if !vertical {
+            self.view.desired_column = None;
+        }
+        if self
+            .vim
+            .as_ref()
+            .is_some_and(|vim| vim.mode != Mode::Insert)
+        {
+            self.clamp_vim_cursor();
+        }
+        self.scroll();
+        if self.quit_times < self.config.quit_times {
+            self.quit_times = self.config.quit_times;
+        }
+        Ok(true)
+    }
+
+    /// Runs a normal or visual mode command. Returns true for vertical
+    /// movements, which keep the desired column.
+    fn run_vim_command(&mut self, command: Command) -> Result<bool, Error> {
+        let Some(vim) = &mut self.vim else {
+            return Ok(false);
+        };
+        let keeps_visual = matches!(
+            command,
+            Command::Move(..) | Command::OperateSelection(_) | Command::Visual(_)
+        );
+        if vim.mode.is_visual() && !keeps_visual {
+            vim.mode = Mode::Normal;
         }
+        let cursor = self.view.cursor_position;
+        match command {
+            Command::Move(motion, count) if motion.is_vertical() => {
+                let key = if motion == vim::Motion::Up {
+                    KeyCode::Up
+                } else {
+                    KeyCode::Down
+                };
+                for _ in 0..count.unwrap_or(1) {
+                    self.move_cursor(key);
+                }
+                return Ok(true);
+            }
+            Command::Move(motion, count) => {
+                if let Some((target, _)) = vim::target(self.document(), cursor, motion, count) {
+                    self.view.cursor_position = target;
+                }
+            }
+            Command::Operate(operator, motion, count) => {
+                let on_word = self
+                    .document()
+                    .row(cursor.y)
+                    .is_some_and(|row| !row.slice(cursor.x, cursor.x + 1).trim().is_empty());
+                let target = if operator == Operator::Change
+                    && motion == vim::Motion::WordForward
+                    && on_word
+                {
+                    // `cw` on a word changes up to its end, like `ce`.
+                    Some((
+                        vim::change_word_end(self.document(), cursor, count),
+                        Span::Inclusive,
+                    ))
+                } else {
+                    vim::target(self.document(), cursor, motion, count)
+                };
+                if let Some((target, span)) = target {
+                    let (start, end) = ordered(cursor, target);
+                    self.apply_operator(operator, start, end, span);
+                }
+            }
+            Command::OperateSelection(operator) => {
+                let span = if vim.mode == Mode::VisualLine {
+                    Span::Linewise
+                } else {
+                    Span::Inclusive
+                };
+                let (start, end) = ordered(vim.visual_start, cursor);
+                vim.mode = Mode::Normal;
+                self.apply_operator(operator, start, end, span);
+            }
+            Command::Insert(insert) => self.start_insert(insert),
+            Command::Paste { before, count } => self.paste(before, count),
+            Command::Undo(count) => {
+                for _ in 0..count {
+                    let position = self.document_mut().undo();
+                    self.restore_cursor(position, "Already at oldest change");
+                }
+            }
+            Command::Redo(count) => {
+                for _ in 0..count {
+                    let position = self.document_mut().redo();
+                    self.restore_cursor(position, "Already at newest change");
+                }
+            }
+            Command::Repeat(count) => {
+                let keys = vim.last_change();
+                vim.replaying = true;
+                for _ in 0..count {
+                    for key in &keys {
+                        self.process_key(*key)?;
+                    }
+                }
+                if let Some(vim) = &mut self.vim {
+                    vim.replaying = false;
+                }
+            }
+            Command::Visual(mode) if vim.mode == mode => vim.mode = Mode::Normal,
+            Command::Visual(mode) => {
+                if !vim.mode.is_visual() {
+                    vim.visual_start = cursor;
+                }
+                vim.mode = mode;
+            }
+            Command::ExLine => self.vim_command_line()?,
+            Command::Escape => (),
+        }
+        Ok(false)
+    }
+
+    /// Applies `operator` to the text from `start` to `end`, saving it in
+    /// the register first.
+    fn apply_operator(&mut self, operator: Operator, start: Position, end: Position, span: Span) {
+        let row_len = |editor: &Self, y: usize| editor.document().row(y).map_or(0, Row::len);
+        let len = self.document().len();
+        let (start, end, linewise) = if span == Span::Linewise {
+            let end = Position {
+                x: row_len(self, end.y),
+                y: end.y,
+            };
+            (Position { x: 0, y: start.y }, end, true)
+        } else if span == Span::Inclusive && end.x < row_len(self, end.y) {
+            (
+                start,
+                Position {
+                    x: end.x + 1,
+                    ..end
+                },
+                false,
+            )
+        } else if span == Span::Inclusive && end.y + 1 < len {
+            (start, Position { x: 0, y: end.y + 1 }, false)
+        } else if span == Span::Exclusive && end.x == 0 && end.y > start.y {
+            // An exclusive motion ending at the start of a line stops at the
+            // end of the line before.
+            let y = end.y - 1;
+            (
+                start,
+                Position {
+                    x: row_len(self, y),
+                    y,
+                },
+                false,
+            )
+        } else {
+            (start, end, false)
+        };
+        let mut text = self.document().text(&start, &end);
+        if linewise {
+            text.push('\n');
+        }
+        if let Some(vim) = &mut self.vim {
+            vim.register = Register { text, linewise };
+        }
+        let is_empty = start.x == end.x && start.y == end.y;
+        match operator {
+            Operator::Yank => {
+                self.view.cursor_position = if linewise {
+                    Position {
+                        x: self.view.cursor_position.x,
+                        y: start.y,
+                    }
+                } else {
+                    start
+                }
+            }
+            // There are no lines to delete in an empty document.
+            Operator::Delete if linewise && start.y >= len => (),
+            Operator::Delete if linewise => {
+                let (from, to) = if end.y + 1 < len {
+                    (start, Position { x: 0, y: end.y + 1 })
+                } else if start.y > 0 {
+                    let y = start.y - 1;
+                    (
+                        Position {
+                            x: row_len(self, y),
+                            y,
+                        },
+                        end,
+                    )
+                } else {
+                    (start, end)
+                };
+                self.document_mut().replace(&from, &to, "");
+                let y = start.y.min(self.document().len().saturating_sub(1));
+                self.view.cursor_position = Position {
+                    x: vim::first_non_blank(self.document(), y),
+                    y,
+                };
+            }
+            Operator::Delete | Operator::Change => {
+                if !is_empty {
+                    self.document_mut().replace(&start, &end, "");
+                }
+                self.view.cursor_position = start;
+                if operator == Operator::Change {
+                    if let Some(vim) = &mut self.vim {
+                        vim.mode = Mode::Insert;
+                    }
+                }
+            }
+        }
+    }
+
+    fn start_insert(&mut self, insert: Insert) {
+        let Position { x, y } = self.view.cursor_position;
+        let row_len = self.document().row(y).map_or(0, Row::len);
+        self.view.cursor_position = match insert {
+            Insert::Before => Position { x, y },
+            Insert::After => Position {
+                x: (x + 1).min(row_len),
+                y,
+            },
+            Insert::LineStart => Position {
+                x: vim::first_non_blank(self.document(), y),
+                y,
+            },
+            Insert::LineEnd => Position { x: row_len, y },
+            Insert::LineBelow => {
+                let at = Position { x: row_len, y };
+                self.document_mut().replace(&at, &at, "\n")
+            }
+            Insert::LineAbove => {
+                let at = Position { x: 0, y };
+                self.document_mut().replace(&at, &at, "\n");
+                at
+            }
+        };
+        if let Some(vim) = &mut self.vim {
+            vim.mode = Mode::Insert;
+        }
+    }
+
+    /// Puts the register after (or before) the cursor, `count` times.
+    fn paste(&mut self, before: bool, count: usize) {
+        let Some(Register { text, linewise }) = &self.vim.as_ref().map(|vim| &vim.register) else {
+            return;
+        };
+        if text.is_empty() {
+            return;
+        }
+        let (text, linewise) = (text.repeat(count), *linewise);
+        let Position { x, y } = self.view.cursor_position;
+        let row_len = self.document().row(y).map_or(0, Row::len);
+        if linewise {
+            let (at, text, line) = if before || self.document().is_empty() {
+                (Position { x: 0, y }, text, y)
+            } else if y + 1 < self.document().len() {
+                (Position { x: 0, y: y + 1 }, text, y + 1)
+            } else {
+                let text = format!("\n{}", text.strip_suffix('\n').unwrap_or(&text));
+                (Position { x: row_len, y }, text, y + 1)
+            };
+            self.document_mut().replace(&at, &at, &text);
+            self.view.cursor_position = Position {
+                x: vim::first_non_blank(self.document(), line),
+                y: line,
+            };
+        } else {
+            let at = Position {
+                x: if before { x } else { (x + 1).min(row_len) },
+                y,
+            };
+            let end = self.document_mut().replace(&at, &at, &text);
+            self.view.cursor_position = Position {
+                x: end.x.saturating_sub(1),
+                y: end.y,
+            };
+        }
+    }
+
+    /// Reads and runs an ex command such as `:w`, `:q!`, `:wq` or `:42`.
+    fn vim_command_line(&mut self) -> Result<(), Error> {
+        self.set_vim_mode(Mode::Command);
+        let input = self.read_command(":", vim::EX_COMMANDS);
+        self.set_vim_mode(Mode::Normal);
+        let Some(input) = input? else {
+            return Ok(());
+        };
+        let (command, argument) = match input.trim().split_once(' ') {
+            Some((command, argument)) => (command, Some(argument.trim())),
+            None => (input.trim(), None),
+        };
+        let dirty = self.unsaved_buffers() > 0;
+        match command {
+            "w" | "wq" | "x" => {
+                match argument.filter(|name| !name.is_empty()) {
+                    Some(name) => self.save_as(name)?,
+                    None if command == "w" || self.document().is_dirty() => self.save()?,
+                    None => (),
+                }
+                let unsaved = self.unsaved_buffers() > 0;
+                if command != "w" && !unsaved {
+                    self.should_quit = true;
+                }
+            }
+            "e" => match argument.filter(|name| !name.is_empty()) {
+                Some(name) => self.open(name),
+                None => {
+                    self.status_message = StatusMessage::from("E32: No file name".to_string());
+                }
+            },
+            "grep" => match argument.filter(|pattern| !pattern.is_empty()) {
+                Some(pattern) => self.search_project(pattern),
+                None => {
+                    self.status_message = StatusMessage::from("E35: No search pattern".to_string());
+                }
+            },
+            "q" | "qa" if dirty => {
+                self.status_message = StatusMessage::from(
+                    "E37: No write since last change (add ! to override)".to_string(),
+                );
+            }
+            "q" | "qa" | "q!" | "qa!" => self.should_quit = true,
+            _ => self.run_command(&input)?,
+        }
+        Ok(())
+    }
+
+    fn set_vim_mode(&mut self, mode: Mode) {
+        if let Some(vim) = &mut self.vim {
+            vim.mode = mode;
+        }
+    }
+
+    /// Keeps the cursor on a character, as normal mode requires.
+    fn clamp_vim_cursor(&mut self) {
+        let len = self.document().len();
+        let y = self.view.cursor_position.y.min(len.saturating_sub(1));
+        let row_len = self.document().row(y).map_or(0, Row::len);
+        let x = self.view.cursor_position.x.min(row_len.saturating_sub(1));
+        self.view.cursor_position = Position { x, y };
+    }
+
+    /// The range highlighted in the current window, as inclusive positions,
+    /// and whether whole lines are selected.
+    fn selection(&self) -> Option<(Position, Position, bool)> {
+        let cursor = self.view.cursor_position;
+        if let Some(mark) = self.view.mark {
+            // The region stops just before its end, which may be the line
+            // break of the row above.
+            let (start, end) = ordered(mark, cursor);
+            let last = match (end.x, end.y) {
+                (0, 0) => return None,
+                (0, y) => Position {
+                    x: usize::MAX,
+                    y: y - 1,
+                },
+                (x, y) => Position { x: x - 1, y },
+            };
+            return ((start.y, start.x) < (end.y, end.x)).then_some((start, last, false));
+        }
+        let vim = self.vim.as_ref().filter(|vim| vim.mode.is_visual())?;
+        let (start, end) = ordered(vim.visual_start, cursor);
+        Some((start, end, vim.mode == Mode::VisualLine))
+    }
+
+    fn document(&self) -> &Document {
+        &self.buffers[self.current_buffer].document
+    }
+
+    fn document_mut(&mut self) -> &mut Document {
+        &mut self.buffers[self.current_buffer].document
+    }
+
+    /// Opens file `name` in a new buffer, or switches to it if it is already
+    /// open. An unnamed, unmodified current buffer is replaced instead of
+    /// kept around.
+    fn open(&mut self, name: &str) {
+        let path = path::expand_home(name);
+        if let Some(index) = self.buffer_index(&path) {
+            self.switch_buffer(index);
+            let name = self.buffer_name(index);
+            self.status_message = StatusMessage::from(format!("Buffer {}: {name}", index + 1));
+            return;
+        }
+        let name = path.to_string_lossy();
+        let exists = path.exists();
+        let buffer = match open_buffer(&name) {
+            Ok(buffer) => buffer,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: {error}"));
+                return;
+            }
+        };
+        let is_listing = buffer.listing.is_some();
+        if self.document().file_name.is_none()
+            && !self.document().is_dirty()
+            && self.document().is_empty()
+        {
+            self.buffers[self.current_buffer] = buffer;
+            self.view = View::default();
+        } else {
+            self.buffers.push(buffer);
+            self.switch_buffer(self.buffers.len() - 1);
+        }
+        let message = if is_listing {
+            self.listing_help().to_string()
+        } else if exists {
+            format!("Opened {name}")
+        } else {
+            format!("New file: {name}")
+        };
+        self.status_message = StatusMessage::from(message);
+    }
+
+    /// The buffer showing the file at `path`, if one does.
+    fn buffer_index(&self, path: &Path) -> Option<usize> {
+        let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let path = canonical(path);
+        self.buffers.iter().position(|buffer| {
+            buffer
+                .document
+                .file_name
+                .as_deref()
+                .is_some_and(|name| canonical(Path::new(name)) == path)
+        })
+    }
+
+    /// Handles the keys of directory listings. Returns false for other
+    /// buffers and for keys that go through the keymap, such as cursor
+    /// movement.
+    fn process_listing_key(&mut self, key: KeyEvent) -> Result<bool, Error> {
+        let Some(listing) = &self.buffers[self.current_buffer].listing else {
+            return Ok(false);
+        };
+        if !(key.modifiers - KeyModifiers::SHIFT).is_empty() || !self.claims_key(key) {
+            return Ok(false);
+        }
+        let vim_letters = self
+            .vim
+            .as_ref()
+            .is_some_and(|vim| vim.mode != Mode::Insert);
+        if vim_letters && matches!(key.code, KeyCode::Char(_)) {
+            return Ok(false);
+        }
+        let entry = listing.entry(self.view.cursor_position.y);
+        let dir = listing.path.clone();
+        match (key.code, entry) {
+            (KeyCode::Enter, Some((entry, path))) if entry.is_dir => {
+                let select = (entry.name == "..").then(|| file_name(&dir));
+                self.show_listing(&path, select.as_deref());
+            }
+            (KeyCode::Enter, Some((_, path))) => self.open(&path.to_string_lossy()),
+            (KeyCode::Backspace | KeyCode::Char('-'), _) => {
+                if let Some(parent) = dir.parent() {
+                    self.show_listing(parent, Some(&file_name(&dir)));
+                }
+            }
+            (KeyCode::Char('n'), _) => self.create_entry(&dir, false)?,
+            (KeyCode::Char('+'), _) => self.create_entry(&dir, true)?,
+            (KeyCode::Char('r'), Some((entry, path))) if entry.name != ".." => {
+                let name = entry.name.clone();
+                self.rename_entry(&dir, &name, &path)?;
+            }
+            (KeyCode::Char('d') | KeyCode::Delete, Some((entry, path))) if entry.name != ".." => {
+                let name = if entry.is_dir {
+                    format!("{}/ and everything in it", entry.name)
+                } else {
+                    entry.name.clone()
+                };
+                if self.confirm(&format!("Delete {name}?"))? {
+                    let result = if path.is_dir() {
+                        fs::remove_dir_all(&path)
+                    } else {
+                        fs::remove_file(&path)
+                    };
+                    let y = self.view.cursor_position.y;
+                    self.show_listing(&dir, None);
+                    self.view.cursor_position.y = y.min(self.document().len().saturating_sub(1));
+                    if let Err(error) = result {
+                        self.status_message =
+                            StatusMessage::from(format!("ERR: {}", describe_error(&error)));
+                    }
+                }
+            }
+            (KeyCode::Enter | KeyCode::Char('r' | 'd') | KeyCode::Delete, _) => (),
+            _ => return Ok(false),
+        }
+        self.scroll();
+        Ok(true)
+    }
+
+    fn listing_help(&self) -> &'static str {
+        if self.vim.is_some() {
+            VIM_LISTING_HELP
+        } else {
+            LISTING_HELP
+        }
+    }
+
+    /// Whether a listing or search results buffer may act on `key` itself:
+    /// it must not continue a key sequence or Vim command, and must not be
+    /// bound to anything but an edit, which a read-only buffer ignores.
+    fn claims_key(&self, key: KeyEvent) -> bool {
+        if !self.pending_keys.is_empty() || self.vim.as_ref().is_some_and(Vim::is_pending) {
+            return false;
+        }
+        match self.keymap.lookup(&[KeyChord::from(key)]) {
+            Lookup::Action(action) => action.edits_text(),
+            Lookup::Prefix => false,
+            Lookup::Unbound => true,
+        }
+    }
+
+    /// Replaces the current listing with one of `dir`, with the cursor on
+    /// entry `select` if given.
+    fn show_listing(&mut self, dir: &Path, select: Option<&str>) {
+        match Listing::read(dir) {
+            Ok(listing) => {
+                let y = select.and_then(|name| listing.position(name)).unwrap_or(0);
+                self.buffers[self.current_buffer] = Buffer::from_listing(listing);
+                self.view = View::default();
+                self.view.cursor_position.y = y;
+                self.status_message = StatusMessage::from(self.listing_help().to_string());
+            }
+            Err(error) => {
+                let message = format!(
+                    "ERR: Could not list {}: {}",
+                    dir.display(),
+                    describe_error(&error)
+                );
+                self.status_message = StatusMessage::from(message);
+            }
+        }
+    }
+
+    /// Asks for the name of a new file, or directory if `is_dir`, and
+    /// creates it in `dir`.
+    fn create_entry(&mut self, dir: &Path, is_dir: bool) -> Result<(), Error> {
+        let prompt = if is_dir {
+            "New directory: "
+        } else {
+            "New file: "
+        };
+        let Some(name) = self.promt(prompt)? else {
+            return Ok(());
+        };
+        let path = dir.join(&name);
+        let result = if path.exists() {
+            Err(format!("{name} already exists"))
+        } else if is_dir {
+            fs::create_dir_all(&path).map_err(|error| describe_error(&error))
+        } else {
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::File::create(&path).map(|_| ()))
+                .map_err(|error| describe_error(&error))
+        };
+        let first = Path::new(&name)
+            .components()
+            .next()
+            .map(|part| part.as_os_str().to_string_lossy().into_owned());
+        self.show_listing(dir, first.as_deref());
+        if let Err(error) = result {
+            self.status_message = StatusMessage::from(format!("ERR: {error}"));
+        }
+        Ok(())
+    }
+
+    /// Renames entry `name` of `dir`, asking before replacing another file.
+    /// Buffers showing the file follow it.
+    fn rename_entry(&mut self, dir: &Path, name: &str, path: &Path) -> Result<(), Error> {
+        let Some(new_name) = self.promt(&format!("Rename {name} to: "))? else {
+            return Ok(());
+        };
+        let target = dir.join(&new_name);
+        if target.exists() && !self.confirm(&format!("{new_name} exists. Replace it?"))? {
+            return Ok(());
+        }
+        let old = fs::canonicalize(path).ok();
+        if let Err(error) = fs::rename(path, &target) {
+            self.status_message = StatusMessage::from(format!("ERR: {}", describe_error(&error)));
+            return Ok(());
+        }
+        for buffer in &mut self.buffers {
+            let file_name = &mut buffer.document.file_name;
+            if file_name
+                .as_deref()
+                .and_then(|name| fs::canonicalize(name).ok())
+                == old
+            {
+                *file_name = Some(target.to_string_lossy().into_owned());
+            }
+        }
+        self.show_listing(dir, Some(&file_name(&target)));
+        Ok(())
+    }
+
+    /// Searches the files under the working directory for `pattern`,
+    /// showing the hits in a results buffer as they are found.
+    fn search_project(&mut self, pattern: &str) {
+        if pattern.is_empty() {
+            return;
+        }
+        let search = ProjectSearch::new(Path::new("."), pattern, None);
+        self.show_search(search, "Enter = go to the hit");
+    }
+
+    /// Asks for text to replace and its replacement, then previews the
+    /// changes to the files under the working directory.
+    fn replace_project(&mut self) -> Result<(), Error> {
+        let Some(pattern) = self.prompt_with("Replace in project: ", Some("search"), None)? else {
+            return Ok(());
+        };
+        if pattern.is_empty() {
+            return Ok(());
+        }
+        let prompt = format!("Replace \"{pattern}\" with: ");
+        let Some(replacement) = self.prompt_with(&prompt, Some("replace"), None)? else {
+            return Ok(());
+        };
+        let search = ProjectSearch::new(Path::new("."), &pattern, Some(&replacement));
+        self.show_search(
+            search,
+            "Space = include or skip | Enter = go to the hit | Save = replace",
+        );
+        Ok(())
+    }
+
+    /// Shows `search` in a new results buffer, or in place of the current
+    /// one if that is a results or scratch buffer.
+    fn show_search(&mut self, search: ProjectSearch, help: &str) {
+        let buffer = Buffer::from_search(search);
+        let current = &self.buffers[self.current_buffer];
+        let scratch = current.document.file_name.is_none()
+            && !current.document.is_dirty()
+            && current.document.is_empty();
+        if current.search.is_some() || scratch {
+            self.buffers[self.current_buffer] = buffer;
+            self.view = View::default();
+        } else {
+            self.buffers.push(buffer);
+            self.switch_buffer(self.buffers.len() - 1);
+        }
+        self.status_message = StatusMessage::from(help.to_string());
+    }
+
+    /// Opens the hit under the cursor when Enter is pressed in a search
+    /// results buffer, and includes or skips it when Space is pressed in a
+    /// replacement preview.
+    fn process_search_key(&mut self, key: KeyEvent) -> bool {
+        if !key.modifiers.is_empty() || !self.claims_key(key) {
+            return false;
+        }
+        let buffer = &mut self.buffers[self.current_buffer];
+        let Some(search) = &mut buffer.search else {
+            return false;
+        };
+        if key.code == KeyCode::Char(' ') && search.replacement.is_some() {
+            if !search.applied {
+                search.toggle(self.view.cursor_position.y);
+                buffer.refresh_search();
+            }
+            return true;
+        }
+        if key.code != KeyCode::Enter {
+            return false;
+        }
+        let Some(hit) = search.hit(self.view.cursor_position.y) else {
+            return true;
+        };
+        let position = Position {
+            x: hit.column,
+            y: hit.line,
+        };
+        let path = hit.path.clone();
+        self.open(&path);
+        if self.buffers[self.current_buffer].search.is_none() {
+            // The file may have changed since the search.
+            self.go_to(position);
+        }
+        true
+    }
+
+    /// Makes the replacements of the current preview that are included.
+    /// Files that aren't open are opened in new buffers, and each file's
+    /// replacements are one undo step, left unsaved. Hits whose text changed
+    /// since the search are skipped.
+    fn apply_replacements(&mut self) {
+        let Some(search) = &self.buffers[self.current_buffer].search else {
+            return;
+        };
+        let message = if !search.is_done() {
+            Some("Still searching")
+        } else if search.applied {
+            Some("The replacements have already been made")
+        } else {
+            None
+        };
+        if let Some(message) = message {
+            self.status_message = StatusMessage::from(message.to_string());
+            return;
+        }
+        let replacement = search.replacement.clone().unwrap_or_default();
+        let hits: Vec<Hit> = search.accepted().cloned().collect();
+
+        let (mut replaced, mut changed, mut skipped) = (0, 0, 0);
+        let mut failed = Vec::new();
+        for file_hits in hits.chunk_by(|a, b| a.path == b.path) {
+            let path = file_hits[0].path.clone();
+            let count = match self.buffer_index(Path::new(&path)) {
+                Some(index) => {
+                    let document = &mut self.buffers[index].document;
+                    search::replace_in_document(document, file_hits, &replacement)
+                }
+                None => match Document::open(&path) {
+                    Ok(mut document) => {
+                        let count =
+                            search::replace_in_document(&mut document, file_hits, &replacement);
+                        if count > 0 {
+                            self.buffers.push(Buffer::from(document));
+                        }
+                        count
+                    }
+                    Err(_) => {
+                        failed.push(path);
+                        continue;
+                    }
+                },
+            };
+            skipped += file_hits.len() - count;
+            if count == 0 {
+                continue;
+            }
+            replaced += count;
+            changed += 1;
+        }
+
+        let buffer = &mut self.buffers[self.current_buffer];
+        if let Some(search) = &mut buffer.search {
+            search.applied = true;
+        }
+        buffer.refresh_search();
+        let mut message = format!(
+            "Replaced {} in {}",
+            search::plural(replaced, "hit"),
+            search::plural(changed, "file")
+        );
+        if changed > 0 {
+            message.push_str(", not saved yet");
+        }
+        if skipped > 0 {
+            message.push_str(&format!(
+                ", skipped {skipped} that changed since the search"
+            ));
+        }
+        if !failed.is_empty() {
+            message.push_str(&format!(", could not change {}", failed.join(", ")));
+        }
+        self.status_message = StatusMessage::from(message);
+    }
+
+    /// Lets the user pick a file under the working directory by fuzzy
+    /// matching its path, previewing the highlighted one, and opens it. The
+    /// list fills in while the directory is still being indexed.
+    fn find_file(&mut self) -> Result<(), Error> {
+        let mut index = FileIndex::new(Path::new("."));
+        let mut filter = Input::default();
+        let mut selected = 0;
+        let mut matches = Vec::new();
+        let mut ranking = PathRanking::new("");
+        let mut preview = (String::new(), Vec::new());
+        let choice = loop {
+            index.poll();
+            let pattern = filter.text().to_string();
+            if ranking.pattern != pattern {
+                ranking = PathRanking::new(&pattern);
+            }
+            if ranking.update(&index.files) {
+                matches = ranking.best(MAX_FINDER_RESULTS);
+            }
+            let match_count = ranking.len();
+            selected = selected.min(matches.len().saturating_sub(1));
+            let indexing = if index.is_done() { "" } else { ", indexing" };
+            let prompt = format!(
+                "Find file ({match_count}/{}{indexing}): ",
+                index.files.len()
+            );
+            self.show_prompt(&prompt, &filter);
+            self.refresh_screen()?;
+            let lines: Vec<&str> = matches
+                .iter()
+                .map(|&file| index.files[file].as_str())
+                .collect();
+            let area = self.editor_area();
+            let list_height = lines.len().min(area.height / 2).max(1);
+            if let Some(&file) = lines.get(selected) {
+                if preview.0 != file {
+                    preview = (
+                        file.to_string(),
+                        finder::preview(Path::new(file), area.height),
+                    );
+                }
+                let rect = Rect {
+                    height: area.height.saturating_sub(list_height),
+                    ..area
+                };
+                self.draw_preview(file, &preview.1, rect);
+            }
+            self.draw_popup(&lines, Some(selected));
+            Terminal::flush()?;
+
+            let event = if index.is_done() {
+                Some(Terminal::read_event()?)
+            } else {
+                Terminal::poll_event(FINDER_REFRESH)?
+            };
+            let Some(Event::Key(pressed_key)) = event else {
+                continue;
+            };
+            if pressed_key.kind == KeyEventKind::Release {
+                continue;
+            }
+            match pressed_key.code {
+                KeyCode::Enter => {
+                    break matches.get(selected).map(|&file| index.files[file].clone())
+                }
+                KeyCode::Esc => break None,
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down => selected = selected.saturating_add(1),
+                _ => {
+                    if filter.handle(pressed_key) && filter.text() != pattern {
+                        selected = 0;
+                    }
+                }
+            }
+        };
+        self.close_prompt();
+        if let Some(file) = choice {
+            self.open(&file);
+        }
+        Ok(())
+    }
+
+    /// Draws `lines` of file `name` in `rect` under a title bar.
+    fn draw_preview(&self, name: &str, lines: &[String], rect: Rect) {
+        if rect.height == 0 {
+            return;
+        }
+        self.set_style("title.active");
+        Terminal::cursor_position(&Position {
+            x: rect.x,
+            y: rect.y,
+        });
+        let fill = "\u{2500}".repeat(rect.width);
+        print!("{}", pad(&format!("\u{2500} {name} {fill}"), rect.width));
+        self.set_style("text");
+        let tab = " ".repeat(self.config.tab_width);
+        for y in 1..rect.height {
+            Terminal::cursor_position(&Position {
+                x: rect.x,
+                y: rect.y + y,
+            });
+            let line = lines
+                .get(y - 1)
+                .map_or(String::new(), |line| line.replace('\t', &tab));
+            print!("{}", pad(&line, rect.width));
+        }
+        self.terminal.reset_style();
+    }
+
+    /// Makes buffer `index` current, keeping the view of the previous one.
+    fn switch_buffer(&mut self, index: usize) {
+        self.buffers[self.current_buffer].view = self.view;
+        self.current_buffer = index;
+        self.view = self.buffers[index].view;
+    }
+
+    fn cycle_buffer(&mut self, step: usize) {
+        let index = (self.current_buffer + step) % self.buffers.len();
+        self.switch_buffer(index);
+        let name = self.buffer_name(index);
+        self.status_message = StatusMessage::from(format!("Buffer {}: {name}", index + 1));
+    }
+
+    fn buffer_name(&self, index: usize) -> String {
+        let document = &self.buffers[index].document;
+        let name = document.file_name.as_deref().unwrap_or("[No Name]");
+        let modified = if document.is_dirty() { " +" } else { "" };
+        format!("{name}{modified}")
+    }
+
+    fn buffer_picker(&mut self) -> Result<(), Error> {
+        let names: Vec<String> = (0..self.buffers.len())
+            .map(|index| format!("{}: {}", index + 1, self.buffer_name(index)))
+            .collect();
+        if let Some(index) = self.pick("Switch to buffer: ", &names, self.current_buffer)? {
+            self.switch_buffer(index);
+        }
+        Ok(())
+    }
+
+    /// Closes the current buffer, asking first if it has unsaved changes.
+    fn close_buffer(&mut self) -> Result<(), Error> {
+        if self.document().is_dirty()
+            && !self.confirm("Buffer has unsaved changes. Close anyway?")?
+        {
+            self.status_message = StatusMessage::from("Close aborted.".to_string());
+            return Ok(());
+        }
+        let closed = self.current_buffer;
+        self.buffers.remove(closed);
+        if self.buffers.is_empty() {
+            self.buffers.push(Buffer::from(Document::default()));
+        }
+        let replacement = closed.min(self.buffers.len() - 1);
+        for window in &mut self.windows {
+            if window.buffer == closed {
+                window.buffer = replacement;
+                window.view = self.buffers[replacement].view;
+            } else if window.buffer > closed {
+                window.buffer -= 1;
+            }
+        }
+        self.current_buffer = replacement;
+        self.view = self.buffers[replacement].view;
+        self.status_message = StatusMessage::from("Buffer closed".to_string());
+        Ok(())
+    }
+
+    /// The screen area shared by all windows.
+    fn editor_area(&self) -> Rect {
+        let size = self.terminal.size();
+        let top = usize::from(self.tab_bar_visible());
+        Rect {
+            x: 0,
+            y: top,
+            width: size.width as usize,
+            height: (size.height as usize).saturating_sub(top + STATUS_ROWS),
+        }
+    }
+
+    fn window_rect(&self, index: usize) -> Rect {
+        let (windows, _) = self.layout.arrange(self.editor_area());
+        windows
+            .into_iter()
+            .find(|(window, _)| *window == index)
+            .map_or_else(|| self.editor_area(), |(_, rect)| rect)
+    }
+
+    /// The part of a window's `rect` used for text, below its title line.
+    fn window_text_area(&self, index: usize, rect: Rect) -> Rect {
+        let title = usize::from(self.windows.len() > 1);
+        let (buffer, _) = self.window_state(index);
+        let gutter = self.gutter_width(&self.buffers[buffer].document);
+        Rect {
+            x: rect.x + gutter.min(rect.width),
+            y: rect.y + title,
+            width: rect.width.saturating_sub(gutter),
+            height: rect.height.saturating_sub(title),
+        }
+    }
+
+    /// Width of the line number column, including its trailing space.
+    fn gutter_width(&self, document: &Document) -> usize {
+        if self.config.line_numbers {
+            document.len().max(1).to_string().len() + 1
+        } else {
+            0
+        }
+    }
+
+    /// The text area of the focused window.
+    fn text_area(&self) -> Rect {
+        self.window_text_area(self.current_window, self.window_rect(self.current_window))
+    }
+
+    fn window_state(&self, index: usize) -> (usize, View) {
+        if index == self.current_window {
+            (self.current_buffer, self.view)
+        } else {
+            let window = &self.windows[index];
+            (window.buffer, window.view)
+        }
+    }
+
+    /// Moves focus to window `index`, keeping the state of the previous one.
+    fn focus_window(&mut self, index: usize) {
+        self.windows[self.current_window] = Window {
+            buffer: self.current_buffer,
+            view: self.view,
+        };
+        self.load_window(index);
+    }
+
+    /// Makes window `index` current without saving the previous window, whose
+    /// cursor may be stale if the buffer was edited from another window.
+    fn load_window(&mut self, index: usize) {
+        let window = &self.windows[index];
+        self.current_window = index;
+        self.current_buffer = window.buffer;
+        self.view = window.view;
+        self.clamp_cursor();
+    }
+
+    fn split_window(&mut self, split: Split) {
+        let new_window = self.windows.len();
+        self.windows.push(Window {
+            buffer: self.current_buffer,
+            view: self.view,
+        });
+        self.layout.split(self.current_window, split, new_window);
+        self.focus_window(new_window);
+    }
+
+    fn close_window(&mut self) {
+        let closed = self.current_window;
+        if !self.layout.close(closed) {
+            self.status_message = StatusMessage::from("Cannot close the last window".to_string());
+            return;
+        }
+        self.windows.remove(closed);
+        self.load_window(closed.saturating_sub(1));
+    }
+
+    fn move_focus(&mut self, direction: Direction) {
+        let area = self.editor_area();
+        if let Some(index) = self.layout.neighbor(area, self.current_window, direction) {
+            self.focus_window(index);
+        }
+    }
+
+    /// Saves every modified buffer that has a file name.
+    fn autosave(&mut self) {
+        let mut saved = 0;
+        let mut failed = Vec::new();
+        for buffer in &mut self.buffers {
+            let document = &mut buffer.document;
+            if !document.is_dirty() || document.file_name.is_none() {
+                continue;
+            }
+            // Leave files changed by other programs to `check_files`.
+            if document.changed_on_disk() {
+                failed.extend(document.file_name.clone());
+                continue;
+            }
+            match document.save() {
+                Ok(()) => saved += 1,
+                Err(_) => failed.extend(document.file_name.clone()),
+            }
+        }
+        if !failed.is_empty() {
+            self.status_message =
+                StatusMessage::from(format!("Autosave failed for {}", failed.join(", ")));
+        } else if saved > 0 {
+            self.status_message = StatusMessage::from(format!("Autosaved {saved} file(s)"));
+        }
+    }
+
+    /// Looks for open files changed by other programs. Unmodified buffers
+    /// are reloaded, and for modified ones the user picks which version to
+    /// keep.
+    fn check_files(&mut self) -> Result<(), Error> {
+        self.last_disk_check = Instant::now();
+        for index in 0..self.buffers.len() {
+            let buffer = &mut self.buffers[index];
+            // Followed files are kept up to date by `follow_files`.
+            if buffer.follow || !buffer.document.changed_on_disk() {
+                continue;
+            }
+            let document = &buffer.document;
+            let name = document.file_name.clone().unwrap_or_default();
+            if !document.is_dirty() {
+                self.reload_buffer(index);
+                continue;
+            }
+            let question = format!("{name} changed on disk. Reload, keep yours or diff? (r/k/d): ");
+            let answer = self
+                .promt(&question)?
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            match answer.as_str() {
+                "r" => self.reload_buffer(index),
+                "d" => {
+                    self.buffers[index].document.keep_changes();
+                    self.show_diff(index);
+                }
+                _ => {
+                    self.buffers[index].document.keep_changes();
+                    self.status_message =
+                        StatusMessage::from(format!("Kept your version of {name}"));
+                }
+            }
+        }
+        // Time spent answering doesn't count toward the next check.
+        self.last_disk_check = Instant::now();
+        Ok(())
+    }
+
+    /// Whether the current buffer shows a file that can be followed.
+    fn can_follow(&self) -> bool {
+        self.document().file_name.is_some() && !self.document().is_read_only()
+    }
+
+    /// Starts or stops adding text appended to the current file as it
+    /// arrives, like `tail -f`, and moves to the end when starting.
+    fn toggle_follow(&mut self) {
+        if !self.can_follow() {
+            self.status_message = StatusMessage::from("Only files can be followed".to_string());
+            return;
+        }
+        let buffer = &mut self.buffers[self.current_buffer];
+        buffer.follow = !buffer.follow;
+        let message = if buffer.follow {
+            // Catch up with changes made while not following.
+            if buffer.document.changed_on_disk() {
+                self.reload_buffer(self.current_buffer);
+            }
+            self.view.cursor_position = Position {
+                x: 0,
+                y: self.document().len().saturating_sub(1),
+            };
+            self.scroll();
+            "Following the end of the file"
+        } else {
+            "Stopped following the file"
+        };
+        self.status_message = StatusMessage::from(message.to_string());
+    }
+
+    /// Adds the text appended to followed files. Views on the last row move
+    /// along with new rows, while views scrolled up stay where they are.
+    fn follow_files(&mut self) {
+        for index in 0..self.buffers.len() {
+            let buffer = &mut self.buffers[index];
+            if !buffer.follow {
+                continue;
+            }
+            let len = buffer.document.len();
+            let name = buffer.document.file_name.clone().unwrap_or_default();
+            let len = match buffer.document.read_appended() {
+                Ok(Some(0)) => continue,
+                Ok(Some(_)) => len,
+                Ok(None) => {
+                    // Truncated or rotated, as log files are, or created.
+                    // All views go to the new end.
+                    self.reload_buffer(index);
+                    self.status_message = StatusMessage::from(format!(
+                        "{name} was truncated or replaced, reloaded it"
+                    ));
+                    0
+                }
+                Err(error) => {
+                    buffer.follow = false;
+                    self.status_message =
+                        StatusMessage::from(format!("Stopped following {name}: {error}"));
+                    continue;
+                }
+            };
+            let height = self.text_area().height;
+            let buffer = &mut self.buffers[index];
+            let new_len = buffer.document.len();
+            follow_view(&mut buffer.view, len, new_len, height);
+            for window in 0..self.windows.len() {
+                if window != self.current_window && self.windows[window].buffer == index {
+                    let height = self
+                        .window_text_area(window, self.window_rect(window))
+                        .height;
+                    follow_view(&mut self.windows[window].view, len, new_len, height);
+                }
+            }
+            if index == self.current_buffer {
+                follow_view(&mut self.view, len, new_len, height);
+                self.scroll();
+            }
+        }
+    }
+
+    /// Replaces buffer `index` with its file as it is now.
+    fn reload_buffer(&mut self, index: usize) {
+        let buffer = &mut self.buffers[index];
+        let name = buffer.document.file_name.clone().unwrap_or_default();
+        if let Err(error) = buffer.document.reload() {
+            self.status_message = StatusMessage::from(format!("Could not reload {name}: {error}"));
+            return;
+        }
+        clamp_view(&mut buffer.view, &buffer.document);
+        for window in &mut self.windows {
+            if window.buffer == index {
+                clamp_view(&mut window.view, &buffer.document);
+            }
+        }
+        if index == self.current_buffer {
+            self.clamp_cursor();
+        }
+        self.status_message = StatusMessage::from(format!("Reloaded {name}, it changed on disk"));
+    }
+
+    /// Reloads the current buffer from its file, asking first if that loses
+    /// changes.
+    fn reload(&mut self) -> Result<(), Error> {
+        if let Some(listing) = &self.buffers[self.current_buffer].listing {
+            let (dir, y) = (listing.path.clone(), self.view.cursor_position.y);
+            self.show_listing(&dir, None);
+            self.view.cursor_position.y = y.min(self.document().len().saturating_sub(1));
+            return Ok(());
+        }
+        let document = self.document();
+        if document.file_name.is_none() || document.is_read_only() {
+            self.status_message = StatusMessage::from("Nothing to reload".to_string());
+            return Ok(());
+        }
+        if document.is_dirty() && !self.confirm("Discard your changes?")? {
+            return Ok(());
+        }
+        self.reload_buffer(self.current_buffer);
+        self.status_message = StatusMessage::from("Reloaded".to_string());
+        Ok(())
+    }
+
+    /// Shows how buffer `index` differs from its file in a new buffer.
+    fn show_diff(&mut self, index: usize) {
+        let document = &self.buffers[index].document;
+        let name = document.file_name.clone().unwrap_or_default();
+        let disk = match fs::read_to_string(&name) {
+            Ok(disk) => disk,
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("Could not read {name}: {error}"));
+                return;
+            }
+        };
+        let ours: Vec<&str> = (0..document.len())
+            .filter_map(|y| document.row(y).map(Row::as_str))
+            .collect();
+        let mut lines = vec![
+            format!("--- {name} (on disk)"),
+            format!("+++ {name} (yours)"),
+        ];
+        lines.extend(diff::unified(&disk.lines().collect::<Vec<_>>(), &ours));
+        let diff = Document::read_only(&format!("Diff {name}"), &lines);
+        self.buffers.push(Buffer::from(diff));
+        self.switch_buffer(self.buffers.len() - 1);
+        self.status_message = StatusMessage::from(
+            "Kept your version; use reload in its buffer to take the one on disk".to_string(),
+        );
+    }
+
+    fn process_mouse(&mut self, mouse_event: MouseEvent) {
+        if mouse_event.kind != MouseEventKind::Down(MouseButton::Left) {
+            return;
+        }
+        if self.tab_bar_visible() && mouse_event.row == 0 {
+            let column = mouse_event.column as usize;
+            let tab = self
+                .tabs()
+                .into_iter()
+                .find(|tab| (tab.x..tab.x + tab.label.chars().count()).contains(&column));
+            if let Some(Tab {
+                buffer: Some(index),
+                ..
+            }) = tab
+            {
+                self.switch_buffer(index);
+            }
+        }
+    }
+
+    fn go_to_prompt(&mut self) -> Result<(), Error> {
+        let prompt = "Go to line[:col], +N, -N or N%: ";
+        if let Some(input) = self.prompt_with(prompt, Some("goto"), None)? {
+            self.go_to_target(&input);
+        }
+        Ok(())
+    }
+
+    fn go_to_target(&mut self, input: &str) {
+        match parse_goto(input, self.view.cursor_position.y, self.document().len()) {
+            Some(position) => self.go_to(position),
+            None => self.status_message = StatusMessage::from(format!("Invalid target: {input}")),
+        }
+    }
+
+    /// Moves the cursor to `position`, clamped to the document, and centers
+    /// it in the viewport.
+    fn go_to(&mut self, position: Position) {
+        let y = position.y.min(self.document().len().saturating_sub(1));
+        let width = self.document().row(y).map_or(0, Row::len);
+        self.view.cursor_position = Position {
+            x: position.x.min(width),
+            y,
+        };
+        self.view.desired_column = None;
+        let half_height = self.text_area().height / 2;
+        if self.config.soft_wrap {
+            let mut top = self.cursor_line();
+            for _ in 0..half_height {
+                top = self.previous_line(top);
+            }
+            (self.view.offset.y, self.view.wrap_offset) = top;
+        } else {
+            self.view.offset.y = y.saturating_sub(half_height);
+        }
+        self.scroll();
+    }
+
+    fn move_word_left(&mut self) {
+        let Position { x, y } = self.view.cursor_position;
+        match self.document().row(y) {
+            Some(row) if x > 0 => self.view.cursor_position.x = row.previous_word_start(x),
+            _ => self.move_cursor(KeyCode::Left),
+        }
+    }
+
+    fn move_word_right(&mut self) {
+        let Position { x, y } = self.view.cursor_position;
+        match self.document().row(y) {
+            Some(row) if x < row.len() => self.view.cursor_position.x = row.next_word_end(x),
+            _ => self.move_cursor(KeyCode::Right),
+        }
+    }
+
+    fn delete_word_left(&mut self) {
+        let end = self.view.cursor_position;
+        self.move_word_left();
+        let start = self.view.cursor_position;
+        if start.y == end.y {
+            for _ in start.x..end.x {
+                self.document_mut().delete(&start);
+            }
+        } else {
+            self.document_mut().delete(&start);
+        }
+    }
+
+    fn delete_word_right(&mut self) {
+        let start = self.view.cursor_position;
+        self.move_word_right();
+        let end = self.view.cursor_position;
+        self.view.cursor_position = start;
+        if start.y == end.y {
+            for _ in start.x..end.x {
+                self.document_mut().delete(&start);
+            }
+        } else {
+            self.document_mut().delete(&start);
+        }
+    }
+
+    fn restore_cursor(&mut self, position: Option<Position>, empty_message: &str) {
+        let Some(position) = position else {
+            self.status_message = StatusMessage::from(empty_message.to_string());
+            return;
+        };
+        self.view.cursor_position = position;
+        self.clamp_cursor();
+    }
+
+    /// Moves the cursor back inside the document if edits left it outside.
+    fn clamp_cursor(&mut self) {
+        let Position { x, y } = self.view.cursor_position;
+        let y = y.min(self.document().len());
+        let width = self.document().row(y).map_or(0, Row::len);
+        self.view.cursor_position = Position { x: x.min(width), y };
+    }
+
+    /// Reflows the selection if there is one, or else the paragraph at the
+    /// cursor.
+    fn reflow_paragraph(&mut self) {
+        let y = self.view.cursor_position.y;
+        let column = self.config.reflow_column;
+        let selection = self.selection();
+        let result = match selection {
+            Some((start, end, _)) => self.document_mut().reflow_rows(start.y, end.y, column),
+            None => self.document_mut().reflow(y, column),
+        };
+        if let Some(vim) = self.vim.as_mut().filter(|vim| vim.mode.is_visual()) {
+            vim.mode = Mode::Normal;
+        }
+        match result {
+            Some(position) => self.view.cursor_position = position,
+            None => self.status_message = StatusMessage::from("No paragraph to reflow".to_string()),
+        }
+    }
+
+    fn toggle_soft_wrap(&mut self) {
+        self.config.soft_wrap = !self.config.soft_wrap;
+        self.view.offset = Position::default();
+        self.view.wrap_offset = 0;
+        let state = if self.config.soft_wrap { "on" } else { "off" };
+        self.status_message = StatusMessage::from(format!("Soft wrap {state}"));
+    }
+
+    fn segments(&self, y: usize) -> Vec<(usize, usize)> {
+        segments(
+            self.document(),
+            y,
+            self.text_area().width,
+            self.config.tab_width,
+        )
+    }
+
+    /// The `(row, segment)` screen line the cursor is on.
+    fn cursor_line(&self) -> (usize, usize) {
+        let Position { x, y } = self.view.cursor_position;
+        let segments = self.segments(y);
+        let segment = segments
+            .iter()
+            .position(|&(start, end)| x >= start && x < end)
+            .unwrap_or(segments.len() - 1);
+        (y, segment)
     }
 
-    fn draw_row(&self, row: &Row) {
-        let start = self.offset.x;
-        let width = self.terminal.size().width as usize;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{row}\r")
+    fn next_line(&self, line: (usize, usize)) -> (usize, usize) {
+        next_line(
+            self.document(),
+            self.text_area().width,
+            self.config.tab_width,
+            line,
+        )
     }
 
-    fn process_keypress(&mut self) -> Result<(), Error> {
-        let pressed_key = Terminal::read_key()?;
-        if pressed_key.kind == KeyEventKind::Release {
-            return Ok(());
-        }
-        match (pressed_key.modifiers, pressed_key.code) {
-            (KeyModifiers::CONTROL, KeyCode::Char('q')) => {
-                if self.quit_times > 0 && self.document.is_dirty() {
-                    self.status_message = StatusMessage::from(format!(
-                        "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit",
-                        self.quit_times
-                    ));
-                    self.quit_times -= 1;
-                    return Ok(());
-                }
-                self.should_quit = true
-            }
-            (KeyModifiers::CONTROL, KeyCode::Char('s')) => self.save(),
-            (_, KeyCode::Char(c)) => {
-                self.document.insert(&self.cursor_position, c);
-                self.move_cursor(KeyCode::Right);
+    fn previous_line(&self, line: (usize, usize)) -> (usize, usize) {
+        previous_line(
+            self.document(),
+            self.text_area().width,
+            self.config.tab_width,
+            line,
+        )
+    }
+
+    /// Moves the cursor by screen lines when soft wrap is enabled. Returns
+    /// false for keys that behave the same with and without wrapping.
+    fn move_cursor_wrapped(&mut self, key_code: KeyCode) -> bool {
+        let (y, segment) = self.cursor_line();
+        let segments = self.segments(y);
+        let (start, _) = segments[segment];
+        let last_x = |segments: &[(usize, usize)], segment: usize| {
+            let (start, end) = segments[segment];
+            if segment + 1 < segments.len() {
+                end.saturating_sub(1).max(start)
+            } else {
+                end
             }
-            (KeyModifiers::NONE, KeyCode::Delete) => self.document.delete(&self.cursor_position),
-            (KeyModifiers::NONE, KeyCode::Backspace) => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(KeyCode::Left);
-                    self.document.delete(&self.cursor_position);
-                }
+        };
+        let column = self.view.desired_column.unwrap_or_else(|| {
+            self.column_at(y, self.view.cursor_position.x)
+                .saturating_sub(self.column_at(y, start))
+        });
+        let target = match key_code {
+            KeyCode::Up => self.previous_line((y, segment)),
+            KeyCode::Down if y < self.document().len() => self.next_line((y, segment)),
+            KeyCode::Down => (y, segment),
+            KeyCode::Home => {
+                self.view.cursor_position.x = start;
+                return true;
             }
-            (KeyModifiers::NONE, KeyCode::Enter) => {
-                self.document.insert_newline(&self.cursor_position);
-                self.move_cursor(KeyCode::Down);
-                self.move_cursor(KeyCode::Home);
+            KeyCode::End => {
+                self.view.cursor_position.x = last_x(&segments, segment);
+                return true;
             }
-            (KeyModifiers::NONE, KeyCode::Up)
-            | (KeyModifiers::NONE, KeyCode::Down)
-            | (KeyModifiers::NONE, KeyCode::Left)
-            | (KeyModifiers::NONE, KeyCode::Right)
-            | (KeyModifiers::NONE, KeyCode::PageUp)
-            | (KeyModifiers::NONE, KeyCode::PageDown)
-            | (KeyModifiers::NONE, KeyCode::End)
-            | (KeyModifiers::NONE, KeyCode::Home) => self.move_cursor(pressed_key.code),
-            _ => (),
-        }
-        self.scroll();
-        if self.quit_times < QUIT_TIMES {
-            self.quit_times = QUIT_TIMES;
-            self.status_message = StatusMessage::from(String::new());
-        }
-        Ok(())
+            _ => return false,
+        };
+        self.view.desired_column = Some(column);
+        let (target_y, target_segment) = target;
+        let target_segments = self.segments(target_y);
+        let (target_start, _) = target_segments[target_segment];
+        let x = self
+            .index_at_column(target_y, self.column_at(target_y, target_start) + column)
+            .min(last_x(&target_segments, target_segment));
+        self.view.cursor_position = Position { x, y: target_y };
+        true
     }
 
     fn move_cursor(&mut self, key_code: KeyCode) {
-        let Position { mut x, mut y } = self.cursor_position;
-        let terminal_height = self.terminal.size().height as usize;
-        let height = self.document.len();
-        let mut width = if let Some(row) = self.document.row(y) {
+        if self.config.soft_wrap && self.move_cursor_wrapped(key_code) {
+            return;
+        }
+        let Position { mut x, mut y } = self.view.cursor_position;
+        let terminal_height = self.text_area().height;
+        let height = self.document().len();
+        let mut width = if let Some(row) = self.document().row(y) {
             row.len()
         } else {
             0
         };
+        let desired_column = if is_vertical(key_code) {
+            let column = self
+                .view
+                .desired_column
+                .unwrap_or_else(|| self.column_at(y, x));
+            self.view.desired_column = Some(column);
+            Some(column)
+        } else {
+            None
+        };
         match key_code {
             KeyCode::Up => y = y.saturating_sub(1),
-            KeyCode::Down => {
-                if y < height {
-                    y = y.saturating_add(1);
-                }
-            }
+            KeyCode::Down if y < height => y = y.saturating_add(1),
             KeyCode::Left => {
                 if x > 0 {
                     x -= 1
                 } else if y > 0 {
                     y -= 1;
-                    if let Some(row) = self.document.row(y) {
+                    if let Some(row) = self.document().row(y) {
                         x = row.len();
                     } else {
                         x = 0;
@@ -265,8 +2617,11 @@ impl Editor {
             KeyCode::Home => x = 0,
             _ => (),
         };
+        if let Some(column) = desired_column {
+            x = self.index_at_column(y, column);
+        }
 
-        width = if let Some(row) = self.document.row(y) {
+        width = if let Some(row) = self.document().row(y) {
             row.len()
         } else {
             0
@@ -275,52 +2630,205 @@ impl Editor {
             x = width;
         }
 
-        self.cursor_position = Position { x, y }
+        self.view.cursor_position = Position { x, y }
     }
 
     fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let offset = &mut self.offset;
-
+        if self.config.soft_wrap {
+            self.scroll_wrapped();
+            return;
+        }
+        let Position { x, y } = self.view.cursor_position;
+        let Rect { width, height, .. } = self.text_area();
+        let offset = &mut self.view.offset;
         if y < offset.y {
             offset.y = y;
         } else if y >= offset.y.saturating_add(height) {
             offset.y = y.saturating_sub(height).saturating_add(1);
         }
 
+        let columns = self
+            .document()
+            .row(y)
+            .map_or_else(|| vec![0], |row| row.columns(self.config.tab_width));
+        let offset = &mut self.view.offset;
         if x < offset.x {
             offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        } else {
+            // A text area can be 0 columns wide in a narrow split; the
+            // cursor's own column is still shown.
+            let width = width.max(1);
+            let x = x.min(columns.len() - 1);
+            if columns[x] - columns[offset.x.min(x)] >= width {
+                offset.x = (offset.x..x)
+                    .find(|&start| columns[x] - columns[start] < width)
+                    .unwrap_or(x);
+            }
+        }
+    }
+
+    /// Screen column of grapheme `x` in row `y`, counted from the row start.
+    fn column_at(&self, y: usize, x: usize) -> usize {
+        self.document()
+            .row(y)
+            .map_or(0, |row| row.column(x, self.config.tab_width))
+    }
+
+    fn index_at_column(&self, y: usize, column: usize) -> usize {
+        self.document()
+            .row(y)
+            .map_or(0, |row| row.index_at_column(column, self.config.tab_width))
+    }
+
+    fn scroll_wrapped(&mut self) {
+        let height = self.text_area().height;
+        let cursor = self.cursor_line();
+        self.view.wrap_offset = self
+            .view
+            .wrap_offset
+            .min(self.segments(self.view.offset.y).len() - 1);
+        if cursor < (self.view.offset.y, self.view.wrap_offset) {
+            (self.view.offset.y, self.view.wrap_offset) = cursor;
+            return;
+        }
+        let mut top = cursor;
+        for _ in 1..height {
+            top = self.previous_line(top);
+        }
+        if top > (self.view.offset.y, self.view.wrap_offset) {
+            (self.view.offset.y, self.view.wrap_offset) = top;
+        }
+    }
+
+    fn tab_bar_visible(&self) -> bool {
+        self.config.tab_bar && self.buffers.len() > 1
+    }
+
+    /// Lays out one tab per buffer, scrolling the list so the current buffer
+    /// stays visible. Hidden tabs are shown as `<` or `>` markers.
+    fn tabs(&self) -> Vec<Tab> {
+        let width = self.terminal.size().width as usize;
+        let labels: Vec<String> = (0..self.buffers.len())
+            .map(|index| {
+                let document = &self.buffers[index].document;
+                let name = document.file_name.as_deref().map_or("[No Name]", |name| {
+                    Path::new(name)
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .unwrap_or(name)
+                });
+                let name = if name.chars().count() > MAX_TAB_NAME {
+                    let mut name: String = name.chars().take(MAX_TAB_NAME - 1).collect();
+                    name.push('\u{2026}');
+                    name
+                } else {
+                    name.to_string()
+                };
+                let modified = if document.is_dirty() { "+" } else { "" };
+                format!(" {name}{modified} ")
+            })
+            .collect();
+        let len = |label: &String| label.chars().count();
+        let mut first = 0;
+        while first < self.current_buffer
+            && usize::from(first > 0)
+                + labels[first..=self.current_buffer]
+                    .iter()
+                    .map(len)
+                    .sum::<usize>()
+                > width.saturating_sub(1)
+        {
+            first += 1;
+        }
+        let mut tabs = Vec::new();
+        let mut x = 0;
+        if first > 0 {
+            tabs.push(Tab {
+                buffer: None,
+                x,
+                label: "<".to_string(),
+            });
+            x += 1;
+        }
+        for (index, label) in labels.iter().enumerate().skip(first) {
+            let more = usize::from(index + 1 < labels.len());
+            if x + len(label) + more > width && index != first {
+                tabs.push(Tab {
+                    buffer: None,
+                    x,
+                    label: ">".to_string(),
+                });
+                break;
+            }
+            tabs.push(Tab {
+                buffer: Some(index),
+                x,
+                label: label.chars().take(width.saturating_sub(x)).collect(),
+            });
+            x += len(label);
+        }
+        tabs
+    }
+
+    fn draw_tab_bar(&self) {
+        let width = self.terminal.size().width as usize;
+        Terminal::cursor_position(&Position::default());
+        self.set_style("tab");
+        print!("{}", " ".repeat(width));
+        for tab in self.tabs() {
+            Terminal::cursor_position(&Position { x: tab.x, y: 0 });
+            if tab.buffer == Some(self.current_buffer) {
+                self.set_style("tab.active");
+            } else {
+                self.set_style("tab");
+            }
+            print!("{}", tab.label);
         }
+        self.terminal.reset_style();
     }
 
     fn draw_status_bar(&self) {
         let mut status;
         let width = self.terminal.size().width as usize;
-        let modified_indicator = if self.document.is_dirty() {
+        let modified_indicator = if self.document().is_dirty() {
             " (modified)"
         } else {
             ""
         };
+        let follow_indicator = match self.buffers[self.current_buffer].follow {
+            false => "",
+            // Scrolled up from the end, so new rows don't move the view.
+            true if self.view.cursor_position.y + 1 < self.document().len() => " [follow paused]",
+            true => " [following]",
+        };
         let mut file_name = "[No Name]".to_string();
-        if let Some(name) = &self.document.file_name {
+        if let Some(name) = &self.document().file_name {
             file_name = name.clone();
             file_name.truncate(20);
         }
+        if self.buffers.len() > 1 {
+            file_name = format!(
+                "[{}/{}] {file_name}",
+                self.current_buffer + 1,
+                self.buffers.len()
+            );
+        }
         status = format!(
-            "{} - {} lines{}",
+            "{} - {} lines{}{}",
             file_name,
-            self.document.len(),
-            modified_indicator
+            self.document().len(),
+            modified_indicator,
+            follow_indicator
         );
-        let line_indicator = format!(
+        let mut line_indicator = format!(
             "{}/{}",
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
+            self.view.cursor_position.y.saturating_add(1),
+            self.document().len()
         );
+        if let Some(vim) = &self.vim {
+            status = format!("{} {status}", vim.mode.name());
+            line_indicator = format!("{}  {line_indicator}", vim.pending());
+        }
         let len = status.len() + line_indicator.len();
         if width > len {
             status.push_str(&" ".repeat(width.saturating_sub(len)));
@@ -328,77 +2836,837 @@ impl Editor {
         status = format!("{status}{line_indicator}");
         status.truncate(width);
 
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
+        self.set_style("status");
         println!("{status}\r");
-        Terminal::reset_bg_color();
-        Terminal::reset_fg_color();
+        self.terminal.reset_style();
     }
 
     fn draw_message_bar(&self) {
-        Terminal::set_bg_color(MESSAGE_BG_COLOR);
+        self.set_style("message");
         Terminal::clear_current_line();
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
+        if Instant::now() - message.time < self.config.message_timeout {
+            let width = self.terminal.size().width as usize;
+            let text: String = message.text.graphemes(true).take(width).collect();
             print!("{text}");
         }
-        Terminal::reset_bg_color();
+        self.terminal.reset_style();
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        let preview = &self.buffers[self.current_buffer].search;
+        if preview
+            .as_ref()
+            .is_some_and(|search| search.replacement.is_some())
+        {
+            self.apply_replacements();
+            return Ok(());
+        }
+        if self.pipe_output.is_some() && self.buffers[self.current_buffer].piped {
+            self.status_message = StatusMessage::from(
+                "This buffer is written to standard output on quit".to_string(),
+            );
+            return Ok(());
+        }
+        let Some(name) = self.document().file_name.clone() else {
+            let completer: Completer = &path::complete;
+            return match self.prompt_with("Save as: ", Some("saveas"), Some(completer))? {
+                Some(name) => self.save_as(&name),
+                None => {
+                    self.status_message = StatusMessage::from("Save aborted.".to_string());
+                    Ok(())
+                }
+            };
+        };
+        if self.document_mut().changed_on_disk()
+            && !self.confirm(&format!("{name} changed on disk. Overwrite it?"))?
+        {
+            self.status_message = StatusMessage::from("Save aborted.".to_string());
+            return Ok(());
+        }
+        self.write_file(&name)
+    }
+
+    /// Saves the current buffer under `name`, asking before overwriting
+    /// another file or creating missing directories.
+    fn save_as(&mut self, name: &str) -> Result<(), Error> {
+        let path = path::expand_home(name);
+        if self.buffer_index(&path) == Some(self.current_buffer) {
+            return self.save();
+        }
+        if path.exists() {
+            let question = format!("{} exists. Overwrite?", path.display());
+            if !self.confirm(&question)? {
+                self.status_message = StatusMessage::from("Save aborted.".to_string());
+                return Ok(());
+            }
+        }
+        self.write_file(&path.to_string_lossy())
     }
 
-    fn save(&mut self) {
-        if self.document.file_name.is_none() {
-            let new_name = self.promt("Save as: ").unwrap_or(None);
-            if new_name.is_none() {
+    /// Writes the current buffer to the file `name`, asking before creating
+    /// missing directories. The buffer takes the name once it is written.
+    fn write_file(&mut self, name: &str) -> Result<(), Error> {
+        let missing_dir = Path::new(name)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty() && !dir.exists());
+        if let Some(dir) = missing_dir {
+            if !self.confirm(&format!("Create directory {}?", dir.display()))? {
                 self.status_message = StatusMessage::from("Save aborted.".to_string());
+                return Ok(());
+            }
+            if let Err(error) = fs::create_dir_all(dir) {
+                self.status_message =
+                    StatusMessage::from(format!("Could not create {}: {error}", dir.display()));
+                return Ok(());
+            }
+        }
+        self.status_message = match self.document_mut().save_to(name) {
+            Ok(()) => StatusMessage::from("File saved successfully".to_string()),
+            Err(error) => StatusMessage::from(format!("Error writing file: {error}")),
+        };
+        Ok(())
+    }
+
+    /// Asks a yes or no `question`; anything but `y` means no.
+    fn confirm(&mut self, question: &str) -> Result<bool, Error> {
+        let answer = self.promt(&format!("{question} (y/N): "))?;
+        Ok(answer.is_some_and(|answer| answer.eq_ignore_ascii_case("y")))
+    }
+
+    /// Applies a `<setting> <value>` line typed by the user, such as
+    /// `tab_width 4` or `theme light`. Underscores in the name are optional
+    /// and a value that is not valid TOML is taken as a string.
+    fn set_setting(&mut self, input: &str) {
+        let (name, text) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
+        let bare = |name: &str| name.replace(['_', '-'], "");
+        let Some(name) = SETTINGS.iter().find(|setting| bare(setting) == bare(name)) else {
+            self.status_message = StatusMessage::from(format!("Unknown setting: {name}"));
+            return;
+        };
+        let text = text.trim();
+        let value = format!("value = {text}")
+            .parse::<toml::Table>()
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| toml::Value::String(text.to_string()));
+        if let Err(error) = self.config.set(name, &value) {
+            self.status_message = StatusMessage::from(format!("ERR: `{name}` {error}"));
+            return;
+        }
+        match *name {
+            "theme" => {
+                let theme = self.config.theme.clone();
+                self.load_theme(&theme);
                 return;
             }
-            self.document.file_name = new_name;
+            "styles" => self.set_theme(self.theme.clone()),
+            "color_mode" => {
+                let support = self.config.color_mode.unwrap_or_else(ColorSupport::detect);
+                self.terminal.set_color_support(support);
+                let name = match support {
+                    ColorSupport::None => "terminal",
+                    _ => &self.config.theme,
+                };
+                match Theme::load(name) {
+                    Ok(theme) => self.set_theme(theme),
+                    Err(error) => {
+                        self.status_message = StatusMessage::from(format!("ERR: {error}"));
+                        return;
+                    }
+                }
+            }
+            "keybindings" | "keys" => {
+                let mut keymap = Keymap::new(self.config.keybindings);
+                for (keys, action) in &self.config.keys {
+                    keymap.bind(keys.clone(), *action);
+                }
+                self.keymap = keymap;
+                let vim = self.config.keybindings == Profile::Vim;
+                if vim != self.vim.is_some() {
+                    self.vim = vim.then(Vim::default);
+                }
+            }
+            "soft_wrap" => {
+                self.view.offset = Position::default();
+                self.view.wrap_offset = 0;
+            }
+            "quit_times" => self.quit_times = self.config.quit_times,
+            _ => (),
         }
+        self.status_message = StatusMessage::from(format!("{name} = {value}"));
+    }
 
-        if self.document.save().is_ok() {
-            self.status_message = StatusMessage::from("File saved successfully".to_string());
-        } else {
-            self.status_message = StatusMessage::from("Error writing file".to_string());
+    /// Reads a command line with fuzzy completion over the actions and
+    /// runs it.
+    fn command_palette(&mut self) -> Result<(), Error> {
+        if let Some(input) = self.read_command(": ", &[])? {
+            self.run_command(&input)?;
         }
+        Ok(())
     }
 
-    fn promt(&mut self, prompt: &str) -> Result<Option<String>, Error> {
-        let mut result = String::new();
+    /// Reads a command line, showing the commands that fuzzily match the
+    /// first word above the message bar. `extra` are `(name, description)`
+    /// pairs of commands besides the actions. Enter runs what was typed,
+    /// unless Up or Down picked another command since the last edit, which
+    /// then runs with the typed arguments; Tab completes the highlighted
+    /// name.
+    fn read_command(
+        &mut self,
+        prompt: &str,
+        extra: &[(&str, &str)],
+    ) -> Result<Option<String>, Error> {
+        let mut commands: Vec<(String, String, String)> = extra
+            .iter()
+            .map(|&(name, description)| (name.to_string(), description.to_string(), String::new()))
+            .collect();
+        for &action in Action::ALL {
+            let name = match action.usage() {
+                Some(usage) => format!("{} {usage}", action.name()),
+                None => action.name().to_string(),
+            };
+            let keys = self.keymap.keys_for(action).join(", ");
+            commands.push((name, action.description().to_string(), keys));
+        }
+        let names: Vec<&str> = commands
+            .iter()
+            .map(|(name, _, _)| name.split(' ').next().unwrap_or_default())
+            .collect();
+        let name_width = commands
+            .iter()
+            .map(|(name, _, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        let description_width = commands
+            .iter()
+            .map(|(_, description, _)| description.len())
+            .max()
+            .unwrap_or(0);
 
-        loop {
-            self.status_message = StatusMessage::from(format!("{prompt}{result}"));
+        let mut input = Input::default();
+        let mut history = History::load("command");
+        let mut selected = 0;
+        // Whether the highlighted command was picked since the last edit.
+        let mut picked = false;
+        let result = loop {
+            let text = input.text().to_string();
+            let (word, rest) = text.split_once(' ').unwrap_or((&text, ""));
+            let matches = fuzzy::rank(word, names.iter().copied());
+            selected = selected.min(matches.len().saturating_sub(1));
+            self.show_prompt(prompt, &input);
             self.refresh_screen()?;
+            let lines: Vec<String> = matches
+                .iter()
+                .map(|&index| {
+                    let (name, description, keys) = &commands[index];
+                    format!("{name:name_width$}  {description:description_width$}  {keys}")
+                })
+                .collect();
+            let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+            self.draw_popup(&lines, Some(selected));
+            Terminal::flush()?;
+
             let pressed_key = Terminal::read_key()?;
             if pressed_key.kind == KeyEventKind::Release {
                 continue;
             }
+            let highlighted = matches.get(selected).map(|&index| names[index]);
+            let ctrl = pressed_key.modifiers.contains(KeyModifiers::CONTROL);
             match pressed_key.code {
-                KeyCode::Enter => break,
-                KeyCode::Backspace => result.truncate(result.len().saturating_sub(1)),
-                KeyCode::Esc => {
-                    result.truncate(0);
-                    break;
-                }
-                KeyCode::Char(c) => {
-                    if !c.is_control() {
-                        result.push(c);
+                KeyCode::Enter => {
+                    break match highlighted {
+                        _ if word.is_empty() => None,
+                        Some(name) if picked => Some(format!("{name} {rest}")),
+                        _ => Some(text.clone()),
+                    };
+                }
+                KeyCode::Esc => break None,
+                KeyCode::Up => {
+                    selected = selected.saturating_sub(1);
+                    picked = true;
+                }
+                KeyCode::Down => {
+                    selected = selected.saturating_add(1);
+                    picked = true;
+                }
+                KeyCode::Char('p') if ctrl => {
+                    if let Some(entry) = history.previous(&text) {
+                        input.set(entry);
+                        picked = false;
+                    }
+                }
+                KeyCode::Char('n') if ctrl => {
+                    if let Some(entry) = history.next() {
+                        input.set(entry);
+                        picked = false;
+                    }
+                }
+                KeyCode::Tab => {
+                    if let Some(name) = highlighted {
+                        input.set(&format!("{name} {}", rest.trim_start()));
+                    }
+                }
+                _ => {
+                    if input.handle(pressed_key) && input.text() != text {
+                        selected = 0;
+                        picked = false;
+                    }
+                }
+            }
+        };
+        self.close_prompt();
+        let result = result.map(|command| command.trim().to_string());
+        if let Some(command) = &result {
+            history.add(command);
+        }
+        Ok(result)
+    }
+
+    /// Runs a command line: an action name followed by its argument, or a
+    /// line to go to.
+    fn run_command(&mut self, input: &str) -> Result<(), Error> {
+        let (name, argument) = input.split_once(' ').unwrap_or((input, ""));
+        let argument = argument.trim();
+        if name.starts_with(|c: char| c.is_ascii_digit()) {
+            self.go_to_target(input);
+            return Ok(());
+        }
+        let Some(action) = Action::from_name(name) else {
+            self.status_message = StatusMessage::from(format!("Unknown command: {name}"));
+            return Ok(());
+        };
+        if argument.is_empty() {
+            return self.perform(action);
+        }
+        match action {
+            Action::Open => self.open(argument),
+            Action::SearchProject => self.search_project(argument),
+            Action::SaveAs => self.save_as(argument)?,
+            Action::GoToLine => self.go_to_target(argument),
+            Action::SelectTheme => self.load_theme(argument),
+            Action::Set => self.set_setting(argument),
+            _ => {
+                self.status_message =
+                    StatusMessage::from(format!("`{name}` does not take an argument"));
+            }
+        }
+        Ok(())
+    }
+
+    fn promt(&mut self, prompt: &str) -> Result<Option<String>, Error> {
+        self.prompt_with(prompt, None, None)
+    }
+
+    /// Reads a line in the message bar. Up and Down (or Ctrl-P and Ctrl-N)
+    /// browse the saved `history` of that name, and Tab completes with
+    /// `completer`, listing the candidates above the message bar.
+    fn prompt_with(
+        &mut self,
+        prompt: &str,
+        history: Option<&str>,
+        completer: Option<Completer>,
+    ) -> Result<Option<String>, Error> {
+        let mut input = Input::default();
+        let mut history = history.map(History::load);
+        let mut completion = Completion::default();
+        let result = loop {
+            self.show_prompt(prompt, &input);
+            self.refresh_screen()?;
+            if !completion.candidates.is_empty() {
+                let lines: Vec<&str> = completion.candidates.iter().map(String::as_str).collect();
+                self.draw_popup(&lines, completion.selected);
+                Terminal::flush()?;
+            }
+            let pressed_key = Terminal::read_key()?;
+            if pressed_key.kind == KeyEventKind::Release {
+                continue;
+            }
+            let code = match pressed_key.code {
+                KeyCode::Char('p') if pressed_key.modifiers == KeyModifiers::CONTROL => KeyCode::Up,
+                KeyCode::Char('n') if pressed_key.modifiers == KeyModifiers::CONTROL => {
+                    KeyCode::Down
+                }
+                code => code,
+            };
+            match code {
+                KeyCode::Enter => break Some(input.text().to_string()),
+                KeyCode::Esc => break None,
+                KeyCode::Up => {
+                    let text = input.text().to_string();
+                    if let Some(entry) = history.as_mut().and_then(|h| h.previous(&text)) {
+                        input.set(entry);
+                        completion.clear();
+                    }
+                }
+                KeyCode::Down => {
+                    if let Some(entry) = history.as_mut().and_then(History::next) {
+                        input.set(entry);
+                        completion.clear();
+                    }
+                }
+                KeyCode::Tab => {
+                    if let Some(completer) = completer {
+                        completion.complete(&mut input, completer);
+                    }
+                }
+                _ => {
+                    if input.handle(pressed_key) {
+                        completion.clear();
                     }
                 }
-                _ => (),
             }
+        };
+        self.close_prompt();
+        let result = result.filter(|text| !text.is_empty());
+        if let (Some(text), Some(history)) = (&result, &mut history) {
+            history.add(text);
         }
+        Ok(result)
+    }
+
+    /// Shows `prompt` and the text of `input` in the message bar, with the
+    /// cursor at the input's cursor.
+    fn show_prompt(&mut self, prompt: &str, input: &Input) {
+        self.status_message = StatusMessage::from(format!("{prompt}{}", input.text()));
+        self.prompt_cursor = Some(prompt.graphemes(true).count() + input.cursor());
+    }
+
+    fn close_prompt(&mut self) {
+        self.prompt_cursor = None;
         self.status_message = StatusMessage::from(String::new());
-        if result.is_empty() {
-            return Ok(None);
+    }
+
+    /// Lets the user choose one of `items` from a list drawn above the status
+    /// bar, starting at `selected`. Typing narrows the list down to items
+    /// containing the typed text. Returns the index of the chosen item.
+    fn pick(
+        &mut self,
+        prompt: &str,
+        items: &[String],
+        selected: usize,
+    ) -> Result<Option<usize>, Error> {
+        let mut filter = Input::default();
+        let mut selected = selected;
+        let choice = loop {
+            let needle = filter.text().to_lowercase();
+            let matches: Vec<usize> = (0..items.len())
+                .filter(|&index| items[index].to_lowercase().contains(&needle))
+                .collect();
+            selected = selected.min(matches.len().saturating_sub(1));
+            self.show_prompt(prompt, &filter);
+            self.refresh_screen()?;
+            let lines: Vec<&str> = matches.iter().map(|&index| items[index].as_str()).collect();
+            self.draw_popup(&lines, Some(selected));
+            Terminal::flush()?;
+
+            let pressed_key = Terminal::read_key()?;
+            if pressed_key.kind == KeyEventKind::Release {
+                continue;
+            }
+            match pressed_key.code {
+                KeyCode::Enter => break matches.get(selected).copied(),
+                KeyCode::Esc => break None,
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down => selected = selected.saturating_add(1),
+                _ => {
+                    if filter.handle(pressed_key) && filter.text().to_lowercase() != needle {
+                        selected = 0;
+                    }
+                }
+            }
+        };
+        self.close_prompt();
+        Ok(choice)
+    }
+
+    /// Draws `lines` in a box just above the status bar, highlighting the
+    /// `selected` line and scrolling to keep it visible.
+    fn draw_popup(&self, lines: &[&str], selected: Option<usize>) {
+        let area = self.editor_area();
+        let width = area.width;
+        let height = lines.len().min(area.height / 2).max(1);
+        let first = selected.map_or(0, |selected| (selected + 1).saturating_sub(height));
+        let top = (area.y + area.height).saturating_sub(height);
+        for index in 0..height {
+            Terminal::cursor_position(&Position {
+                x: 0,
+                y: top + index,
+            });
+            let line = lines.get(first + index).copied().unwrap_or("(no matches)");
+            let mut text: String = format!(" {line}").chars().take(width).collect();
+            text.push_str(&" ".repeat(width.saturating_sub(text.chars().count())));
+            if selected == Some(first + index) {
+                self.set_style("popup.selected");
+            } else {
+                self.set_style("popup");
+            }
+            print!("{text}");
+        }
+        self.terminal.reset_style();
+        let message_len = self.status_message.text.chars().count();
+        Terminal::cursor_position(&Position {
+            x: self
+                .prompt_cursor
+                .unwrap_or(message_len)
+                .min(width.saturating_sub(1)),
+            y: area.y + area.height + 1,
+        });
+    }
+}
+
+/// Screen-line ranges of row `y` when wrapped at `width`; rows past the end
+/// of the document occupy a single empty line.
+fn segments(document: &Document, y: usize, width: usize, tab_width: usize) -> Vec<(usize, usize)> {
+    match document.row(y) {
+        Some(row) => row.wrap(width, tab_width),
+        None => vec![(0, 0)],
+    }
+}
+
+fn next_line(
+    document: &Document,
+    width: usize,
+    tab_width: usize,
+    (y, segment): (usize, usize),
+) -> (usize, usize) {
+    if segment + 1 < segments(document, y, width, tab_width).len() {
+        (y, segment + 1)
+    } else {
+        (y.saturating_add(1), 0)
+    }
+}
+
+fn previous_line(
+    document: &Document,
+    width: usize,
+    tab_width: usize,
+    (y, segment): (usize, usize),
+) -> (usize, usize) {
+    if segment > 0 {
+        (y, segment - 1)
+    } else if y > 0 {
+        (y - 1, segments(document, y - 1, width, tab_width).len() - 1)
+    } else {
+        (0, 0)
+    }
+}
+
+/// Truncates or pads `text` with spaces to exactly `width` graphemes.
+fn pad(text: &str, width: usize) -> String {
+    let mut result: String = text.graphemes(true).take(width).collect();
+    let len = result.graphemes(true).count();
+    result.push_str(&" ".repeat(width - len));
+    result
+}
+
+/// Parses a go-to target into a zero-based position: `line`, `line:col`,
+/// `+N`/`-N` lines relative to `current`, or `N%` of a document of `len`
+/// rows.
+fn parse_goto(input: &str, current: usize, len: usize) -> Option<Position> {
+    let input = input.trim();
+    if let Some(percent) = input.strip_suffix('%') {
+        let percent: usize = percent.trim().parse().ok()?;
+        let y = (len.saturating_mul(percent.min(100)) / 100).saturating_sub(1);
+        return Some(Position { x: 0, y });
+    }
+    if let Some(lines) = input.strip_prefix('+') {
+        let y = current.saturating_add(lines.parse().ok()?);
+        return Some(Position { x: 0, y });
+    }
+    if let Some(lines) = input.strip_prefix('-') {
+        let y = current.saturating_sub(lines.parse().ok()?);
+        return Some(Position { x: 0, y });
+    }
+    let (line, column) = match input.split_once(':') {
+        Some((line, column)) => (line, column.parse().ok()?),
+        None => (input, 1),
+    };
+    let line: usize = line.parse().ok()?;
+    Some(Position {
+        x: usize::saturating_sub(column, 1),
+        y: line.saturating_sub(1),
+    })
+}
+
+/// Splits a `file:line[:col]` argument into the file name and a zero-based
+/// position. Names of existing files are never split.
+fn split_file_position(arg: &str) -> (&str, Option<Position>) {
+    if Path::new(arg).exists() {
+        return (arg, None);
+    }
+    let Some((rest, last)) = arg.rsplit_once(':') else {
+        return (arg, None);
+    };
+    let Ok(last) = last.parse::<usize>() else {
+        return (arg, None);
+    };
+    let line = rest
+        .rsplit_once(':')
+        .and_then(|(name, line)| Some((name, line.parse::<usize>().ok()?)));
+    match line {
+        Some((name, line)) if !name.is_empty() => {
+            let position = Position {
+                x: last.saturating_sub(1),
+                y: line.saturating_sub(1),
+            };
+            (name, Some(position))
         }
-        Ok(Some(result))
+        _ if !rest.is_empty() => {
+            let position = Position {
+                x: 0,
+                y: last.saturating_sub(1),
+            };
+            (rest, Some(position))
+        }
+        _ => (arg, None),
+    }
+}
+
+/// Opens file `name`, or starts an empty document with that name if it does
+/// not exist yet. Errors are worded for the message bar.
+fn open_document(name: &str) -> Result<Document, String> {
+    match Document::open(name) {
+        Ok(document) => Ok(document),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(Document::named(name)),
+        Err(error) => Err(format!("Could not open {name}: {}", describe_error(&error))),
+    }
+}
+
+/// A buffer for file `name`, or a listing if it is a directory.
+fn open_buffer(name: &str) -> Result<Buffer, String> {
+    if !Path::new(name).is_dir() {
+        return open_document(name).map(Buffer::from);
+    }
+    Listing::read(Path::new(name))
+        .map(Buffer::from_listing)
+        .map_err(|error| format!("Could not list {name}: {}", describe_error(&error)))
+}
+
+/// Moves a view of a followed file that was on its last row, of `len`, to
+/// the new last row, keeping it `height` rows from the top at most.
+fn follow_view(view: &mut View, len: usize, new_len: usize, height: usize) {
+    if view.cursor_position.y + 1 < len {
+        return;
+    }
+    let y = new_len.saturating_sub(1);
+    view.cursor_position = Position { x: 0, y };
+    view.offset.y = view.offset.y.max((y + 1).saturating_sub(height));
+    view.wrap_offset = 0;
+}
+
+/// Moves the cursor of `view` back inside `document`.
+fn clamp_view(view: &mut View, document: &Document) {
+    let y = view.cursor_position.y.min(document.len());
+    let width = document.row(y).map_or(0, Row::len);
+    view.cursor_position = Position {
+        x: view.cursor_position.x.min(width),
+        y,
+    };
+}
+
+/// The last component of `path`, or the whole path for roots.
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy())
+        .into_owned()
+}
+
+fn describe_error(error: &std::io::Error) -> String {
+    match error.kind() {
+        ErrorKind::PermissionDenied => "permission denied".to_string(),
+        ErrorKind::InvalidData => "it is not valid UTF-8 text".to_string(),
+        _ => error.to_string(),
+    }
+}
+
+fn key_name(keymap: &Keymap, action: Action) -> String {
+    keymap
+        .keys_for(action)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| action.name().to_string())
+}
+
+/// The graphemes of row `y` inside an inclusive `selection`, where an end
+/// of `usize::MAX` includes the line break.
+fn selected_range(
+    (start, end, linewise): (Position, Position, bool),
+    y: usize,
+) -> Option<(usize, usize)> {
+    if y < start.y || y > end.y {
+        return None;
+    }
+    if linewise {
+        return Some((0, usize::MAX));
+    }
+    let from = if y == start.y { start.x } else { 0 };
+    let to = if y == end.y {
+        end.x.saturating_add(1)
+    } else {
+        usize::MAX
+    };
+    Some((from, to))
+}
+
+/// `a` and `b` with the earlier position first.
+fn ordered(a: Position, b: Position) -> (Position, Position) {
+    if (a.y, a.x) <= (b.y, b.x) {
+        (a, b)
+    } else {
+        (b, a)
     }
 }
 
+/// Keys that move the cursor between lines and keep the desired column.
+fn is_vertical(key_code: KeyCode) -> bool {
+    matches!(
+        key_code,
+        KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown
+    )
+}
+
+/// Reports a problem found before the terminal was set up and exits.
+fn exit_with(message: &str) -> ! {
+    eprintln!("te: {message}");
+    process::exit(1)
+}
+
 fn die(err: &std::io::Error) {
     Terminal::clear_screen();
-    terminal::disable_raw_mode().unwrap();
+    Terminal::restore();
     panic!("{err}")
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn at(position: Option<Position>) -> Option<(usize, usize)> {
+        position.map(|Position { x, y }| (y, x))
+    }
+
+    #[test]
+    fn parse_goto_lines_and_columns() {
+        assert_eq!(at(parse_goto("12", 0, 100)), Some((11, 0)));
+        assert_eq!(at(parse_goto(" 12:5 ", 0, 100)), Some((11, 4)));
+        assert_eq!(at(parse_goto("0", 0, 100)), Some((0, 0)));
+        assert_eq!(at(parse_goto("3:0", 0, 100)), Some((2, 0)));
+        // Past the end is left for the caller to clamp.
+        assert_eq!(at(parse_goto("500", 0, 100)), Some((499, 0)));
+    }
+
+    #[test]
+    fn parse_goto_relative_and_percent() {
+        assert_eq!(at(parse_goto("+5", 10, 100)), Some((15, 0)));
+        assert_eq!(at(parse_goto("-5", 10, 100)), Some((5, 0)));
+        assert_eq!(at(parse_goto("-50", 10, 100)), Some((0, 0)));
+        assert_eq!(at(parse_goto("50%", 0, 200)), Some((99, 0)));
+        assert_eq!(at(parse_goto("250%", 0, 200)), Some((199, 0)));
+        assert_eq!(at(parse_goto("0%", 0, 200)), Some((0, 0)));
+        assert_eq!(
+            at(parse_goto(&format!("+{}", usize::MAX), 10, 100)),
+            Some((usize::MAX, 0))
+        );
+    }
+
+    #[test]
+    fn parse_goto_rejects_other_input() {
+        for input in [
+            "",
+            "abc",
+            "12:",
+            ":3",
+            "1:2:3",
+            "+",
+            "-x",
+            "%",
+            "99999999999999999999999",
+        ] {
+            assert!(parse_goto(input, 0, 100).is_none(), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn split_file_position_forms() {
+        assert_eq!(split_file_position("no-such-file.rs").0, "no-such-file.rs");
+        assert!(split_file_position("no-such-file.rs").1.is_none());
+        let (name, position) = split_file_position("no-such-file.rs:12");
+        assert_eq!((name, at(position)), ("no-such-file.rs", Some((11, 0))));
+        let (name, position) = split_file_position("no-such-file.rs:12:7");
+        assert_eq!((name, at(position)), ("no-such-file.rs", Some((11, 6))));
+        let (name, position) = split_file_position("no-such-file.rs:0:0");
+        assert_eq!((name, at(position)), ("no-such-file.rs", Some((0, 0))));
+    }
+
+    #[test]
+    fn split_file_position_keeps_other_colons() {
+        let (name, position) = split_file_position("C:\\no\\such\\file.rs");
+        assert_eq!((name, at(position)), ("C:\\no\\such\\file.rs", None));
+        let (name, position) = split_file_position("C:\\no\\such\\file.rs:3");
+        assert_eq!(
+            (name, at(position)),
+            ("C:\\no\\such\\file.rs", Some((2, 0)))
+        );
+        let (name, position) = split_file_position("no:such:file:4:2");
+        assert_eq!((name, at(position)), ("no:such:file", Some((3, 1))));
+        let (name, position) = split_file_position("no-such-file.rs:x");
+        assert_eq!((name, at(position)), ("no-such-file.rs:x", None));
+        let (name, position) = split_file_position(":12");
+        assert_eq!((name, at(position)), (":12", None));
+        let (name, position) = split_file_position("no-such-file.rs:99999999999999999999999");
+        assert_eq!(name, "no-such-file.rs:99999999999999999999999");
+        assert!(position.is_none());
+    }
+
+    #[test]
+    fn split_file_position_leaves_existing_names_whole() {
+        let path = env::temp_dir().join(format!("te-test-{}:12", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let name = path.to_str().unwrap();
+        let (split, position) = split_file_position(name);
+        fs::remove_file(&path).unwrap();
+        assert_eq!((split, at(position)), (name, None));
+    }
+
+    #[test]
+    fn args_apply_line_to_the_next_file() {
+        let args = Args::parse(
+            ["+3", "no-such-a.rs", "no-such-b.rs", "--follow", "+x", "-"].map(String::from),
+        );
+        let files: Vec<_> = args
+            .files
+            .iter()
+            .map(|(name, position)| (name.as_str(), at(*position)))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("no-such-a.rs", Some((2, 0))),
+                ("no-such-b.rs", None),
+                ("+x", None)
+            ]
+        );
+        assert!(args.follow && args.stdin && !args.pipe);
+    }
+
+    #[test]
+    fn args_file_position_wins_over_line() {
+        let args = Args::parse(["+3", "no-such-a.rs:8"].map(String::from));
+        assert_eq!(args.files.len(), 1);
+        assert_eq!(at(args.files[0].1), Some((7, 0)));
+        // The +3 was not used up by a file and applies to nothing.
+        let args = Args::parse(["no-such-a.rs", "+3"].map(String::from));
+        assert_eq!(at(args.files[0].1), None);
+    }
+
+    #[test]
+    fn args_report_unknown_keybindings() {
+        let args = Args::parse(["--keybindings=vim".to_string()]);
+        assert!(matches!(args.profile, Some(Ok(Profile::Vim))));
+        let args = Args::parse(["--keybindings=nano".to_string()]);
+        assert!(matches!(args.profile, Some(Err(_))));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const SIZE_UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// The contents of a directory as shown in a buffer, one entry per row:
+/// `..` first unless the directory is a root, then directories, then files.
+pub struct Listing {
+    pub path: PathBuf,
+    pub entries: Vec<Entry>,
+}
+
+impl Listing {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let path = fs::canonicalize(path)?;
+        let mut entries: Vec<Entry> = fs::read_dir(&path)?
+            .flatten()
+            .map(|entry| {
+                // Follow symlinks so links to directories can be entered.
+                let metadata = fs::metadata(entry.path()).ok();
+                Entry {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    is_dir: metadata.as_ref().is_some_and(fs::Metadata::is_dir),
+                    size: metadata.map_or(0, |metadata| metadata.len()),
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            b.is_dir
+                .cmp(&a.is_dir)
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        });
+        if path.parent().is_some() {
+            let parent = Entry {
+                name: "..".to_string(),
+                is_dir: true,
+                size: 0,
+            };
+            entries.insert(0, parent);
+        }
+        Ok(Self { path, entries })
+    }
+
+    /// The name the buffer is shown under.
+    pub fn name(&self) -> String {
+        let path = self.path.to_string_lossy();
+        if path.ends_with('/') {
+            path.into_owned()
+        } else {
+            format!("{path}/")
+        }
+    }
+
+    /// The text of the rows: directories end in `/` and files are followed
+    /// by their size.
+    pub fn lines(&self) -> Vec<String> {
+        let width = self
+            .entries
+            .iter()
+            .map(|entry| entry.name.chars().count() + 1)
+            .max()
+            .unwrap_or(0);
+        self.entries
+            .iter()
+            .map(|entry| {
+                if entry.is_dir {
+                    format!("{}/", entry.name)
+                } else {
+                    format!("{:width$} {:>10}", entry.name, format_size(entry.size))
+                }
+            })
+            .collect()
+    }
+
+    /// The entry on row `y` and its path.
+    pub fn entry(&self, y: usize) -> Option<(&Entry, PathBuf)> {
+        let entry = self.entries.get(y)?;
+        let path = match entry.name.as_str() {
+            ".." => self.path.parent()?.to_path_buf(),
+            name => self.path.join(name),
+        };
+        Some((entry, path))
+    }
+
+    /// The row of the entry called `name`.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < SIZE_UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", SIZE_UNITS[unit])
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use ignore::WalkBuilder;
+
+/// Paths of the files under a directory, collected on a background thread
+/// so that large trees don't block the editor. Files ignored by `.gitignore`
+/// and hidden files are skipped.
+pub struct FileIndex {
+    pub files: Vec<String>,
+    receiver: Receiver<String>,
+    done: bool,
+}
+
+impl FileIndex {
+    pub fn new(root: &Path) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let root = root.to_path_buf();
+        thread::spawn(move || {
+            let walker = WalkBuilder::new(&root).require_git(false).build();
+            for entry in walker.flatten() {
+                if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                    continue;
+                }
+                let path = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+                // The receiver is gone once the finder is closed.
+                if sender.send(path.to_string_lossy().into_owned()).is_err() {
+                    return;
+                }
+            }
+        });
+        Self {
+            files: Vec::new(),
+            receiver,
+            done: false,
+        }
+    }
+
+    /// Adds the files found since the last call. Returns whether any were.
+    pub fn poll(&mut self) -> bool {
+        let before = self.files.len();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(file) => self.files.push(file),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        self.files.len() > before
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+/// The first `count` lines of the file at `path`, or a note saying why
+/// there is nothing to show.
+pub fn preview(path: &Path, count: usize) -> Vec<String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) => return vec![format!("({error})")],
+    };
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).lines().take(count) {
+        match line {
+            Ok(line) if !line.contains('\0') => lines.push(line),
+            _ => return vec!["(binary file)".to_string()],
+        }
+    }
+    lines
+}
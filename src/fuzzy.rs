@@ -0,0 +1,103 @@
+/// Scores how well `pattern` matches `text` as a case-insensitive
+/// subsequence, ignoring spaces in the pattern, or None if it doesn't match.
+/// Higher is better: matches at the start of words and runs of adjacent
+/// matches count extra, while skipped characters count against.
+pub fn score(pattern: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous_match: Option<usize> = None;
+    for wanted in pattern.chars().filter(|c| !c.is_whitespace()) {
+        let wanted = wanted.to_ascii_lowercase();
+        let found = (next..text.len()).find(|&index| text[index].to_ascii_lowercase() == wanted)?;
+        score += 1;
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 8;
+        }
+        match previous_match {
+            Some(previous) if previous + 1 == found => score += 4,
+            Some(previous) => score -= (found - previous - 1).min(4) as i32,
+            None => (),
+        }
+        previous_match = Some(found);
+        next = found + 1;
+    }
+    Some(score - (text.len() / 16) as i32)
+}
+
+/// `score` for a file path, with a bonus when the pattern also matches the
+/// file name alone, so `main` ranks `src/main.rs` above `main/lib.rs`.
+pub fn score_path(pattern: &str, path: &str) -> Option<i32> {
+    let total = score(pattern, path)?;
+    let name = path.rsplit('/').next().unwrap_or(path);
+    Some(total + score(pattern, name).map_or(0, |name_score| name_score / 2 + 8))
+}
+
+/// Indices of the `items` that match `pattern`, best match first.
+pub fn rank<'a>(pattern: &str, items: impl IntoIterator<Item = &'a str>) -> Vec<usize> {
+    rank_with(score, pattern, items)
+}
+
+/// The file paths that match a pattern, best match first, kept up to date
+/// as more paths come in without scoring the earlier ones again.
+pub struct PathRanking {
+    pub pattern: String,
+    /// How many of the paths have been scored.
+    scored: usize,
+    matches: Vec<(i32, usize)>,
+}
+
+impl PathRanking {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            scored: 0,
+            matches: Vec::new(),
+        }
+    }
+
+    /// Scores the `paths` past those seen by earlier calls, which must be
+    /// the same. Returns whether there were any.
+    pub fn update(&mut self, paths: &[String]) -> bool {
+        let Some(new) = paths.get(self.scored..).filter(|new| !new.is_empty()) else {
+            return false;
+        };
+        let first = self.scored;
+        self.matches.extend(
+            new.iter().enumerate().filter_map(|(index, path)| {
+                Some((score_path(&self.pattern, path)?, first + index))
+            }),
+        );
+        self.matches.sort_by_key(|&(score, index)| (-score, index));
+        self.scored = paths.len();
+        true
+    }
+
+    /// How many paths match.
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// Indices of the `count` best matching paths.
+    pub fn best(&self, count: usize) -> Vec<usize> {
+        self.matches
+            .iter()
+            .take(count)
+            .map(|&(_, index)| index)
+            .collect()
+    }
+}
+
+fn rank_with<'a>(
+    score: fn(&str, &str) -> Option<i32>,
+    pattern: &str,
+    items: impl IntoIterator<Item = &'a str>,
+) -> Vec<usize> {
+    let mut matches: Vec<(i32, usize)> = items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, item)| Some((score(pattern, item)?, index)))
+        .collect();
+    matches.sort_by_key(|&(score, index)| (-score, index));
+    matches.into_iter().map(|(_, index)| index).collect()
+}
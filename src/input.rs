@@ -0,0 +1,219 @@
+use std::{fs, path::PathBuf};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::{config::state_dir, Row};
+
+/// How many entries each prompt history keeps.
+const HISTORY_SIZE: usize = 100;
+
+/// Produces the completions of a prompt's text, each a full replacement for
+/// it.
+pub type Completer<'a> = &'a dyn Fn(&str) -> Vec<String>;
+
+/// A single line of text being edited, as in the message bar prompts.
+#[derive(Default)]
+pub struct Input {
+    row: Row,
+    /// Grapheme index of the cursor.
+    cursor: usize,
+}
+
+impl Input {
+    pub fn text(&self) -> &str {
+        self.row.as_str()
+    }
+
+    /// Replaces the text and moves the cursor to its end.
+    pub fn set(&mut self, text: &str) {
+        self.row = Row::from(text);
+        self.cursor = self.row.len();
+    }
+
+    /// Graphemes before the cursor.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Applies an editing or cursor movement key. Returns false for keys it
+    /// does not handle.
+    pub fn handle(&mut self, key: KeyEvent) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let len = self.row.len();
+        match key.code {
+            KeyCode::Left if ctrl || alt => self.cursor = self.row.previous_word_start(self.cursor),
+            KeyCode::Right if ctrl || alt => self.cursor = self.row.next_word_end(self.cursor),
+            KeyCode::Char('b') if alt => self.cursor = self.row.previous_word_start(self.cursor),
+            KeyCode::Char('f') if alt => self.cursor = self.row.next_word_end(self.cursor),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(len),
+            KeyCode::Char('b') if ctrl => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Char('f') if ctrl => self.cursor = (self.cursor + 1).min(len),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::End => self.cursor = len,
+            KeyCode::Char('e') if ctrl => self.cursor = len,
+            KeyCode::Backspace if ctrl || alt => {
+                self.delete_to(self.row.previous_word_start(self.cursor))
+            }
+            KeyCode::Char('w' | 'h') if ctrl => {
+                self.delete_to(self.row.previous_word_start(self.cursor))
+            }
+            KeyCode::Delete if ctrl => self.delete_to(self.row.next_word_end(self.cursor)),
+            KeyCode::Char('d') if alt => self.delete_to(self.row.next_word_end(self.cursor)),
+            KeyCode::Backspace => self.delete_to(self.cursor.saturating_sub(1)),
+            KeyCode::Delete => self.delete_to((self.cursor + 1).min(len)),
+            KeyCode::Char('d') if ctrl => self.delete_to((self.cursor + 1).min(len)),
+            KeyCode::Char('u') if ctrl => self.delete_to(0),
+            KeyCode::Char('k') if ctrl => self.delete_to(len),
+            KeyCode::Char(c) if !ctrl && !alt && !c.is_control() => {
+                self.row.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Deletes the graphemes between the cursor and `to`, leaving the cursor
+    /// at the start of the deleted range.
+    fn delete_to(&mut self, to: usize) {
+        let (start, end) = (self.cursor.min(to), self.cursor.max(to));
+        for _ in start..end {
+            self.row.delete(start);
+        }
+        self.cursor = start;
+    }
+}
+
+/// Earlier entries of a prompt, oldest first, kept across sessions in the
+/// state directory.
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+    /// The entry being shown; `entries.len()` is the text typed before
+    /// browsing started.
+    position: usize,
+    draft: String,
+}
+
+impl History {
+    /// Loads the history called `name`. A missing or unreadable file gives
+    /// an empty history.
+    pub fn load(name: &str) -> Self {
+        let path = state_dir().map(|dir| dir.join("history").join(name));
+        let entries: Vec<String> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        Self {
+            path,
+            position: entries.len(),
+            entries,
+            draft: String::new(),
+        }
+    }
+
+    /// The entry before the one shown, remembering `current` if browsing
+    /// starts here.
+    pub fn previous(&mut self, current: &str) -> Option<&str> {
+        if self.position == 0 {
+            return None;
+        }
+        if self.position == self.entries.len() {
+            self.draft = current.to_string();
+        }
+        self.position -= 1;
+        Some(&self.entries[self.position])
+    }
+
+    /// The entry after the one shown, or the text typed before browsing.
+    pub fn next(&mut self) -> Option<&str> {
+        if self.position >= self.entries.len() {
+            return None;
+        }
+        self.position += 1;
+        Some(self.entries.get(self.position).unwrap_or(&self.draft))
+    }
+
+    /// Adds `entry` as the newest entry and saves the history. Failing to
+    /// save is not worth interrupting the user for.
+    pub fn add(&mut self, entry: &str) {
+        if entry.is_empty() || entry.contains('\n') {
+            return;
+        }
+        self.entries.retain(|existing| existing != entry);
+        self.entries.push(entry.to_string());
+        if self.entries.len() > HISTORY_SIZE {
+            self.entries.remove(0);
+        }
+        self.position = self.entries.len();
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let _ = fs::write(path, self.entries.join("\n") + "\n");
+        }
+    }
+}
+
+/// Tab completion state of a prompt: the first Tab completes as far as all
+/// candidates agree, and further presses cycle through them.
+#[derive(Default)]
+pub struct Completion {
+    pub candidates: Vec<String>,
+    /// The candidate last put into the input while cycling.
+    pub selected: Option<usize>,
+}
+
+impl Completion {
+    pub fn complete(&mut self, input: &mut Input, completer: Completer) {
+        let cycling = self
+            .selected
+            .is_some_and(|selected| self.candidates[selected] == input.text());
+        if cycling
+            || (!self.candidates.is_empty() && input.text() == common_prefix(&self.candidates))
+        {
+            let next = self
+                .selected
+                .map_or(0, |selected| (selected + 1) % self.candidates.len());
+            self.selected = Some(next);
+            input.set(&self.candidates[next]);
+            return;
+        }
+        self.candidates = completer(input.text());
+        self.selected = None;
+        match &self.candidates[..] {
+            [] => (),
+            [only] => {
+                input.set(only);
+                self.candidates.clear();
+            }
+            candidates => input.set(&common_prefix(candidates)),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.candidates.clear();
+        self.selected = None;
+    }
+}
+
+/// The longest text all of `candidates` start with.
+fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|((index, a), b)| *index < len && a == b)
+            .last()
+            .map_or(0, |((index, a), _)| index + a.len_utf8());
+    }
+    first[..len].to_string()
+}
@@ -0,0 +1,490 @@
+use std::{collections::HashMap, fmt};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Declares `Action` together with the name used in config files and a short
+/// description, so the three cannot drift apart.
+macro_rules! actions {
+    ($($action:ident => $name:literal, $description:literal;)*) => {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+        pub enum Action {
+            $($action,)*
+        }
+
+        impl Action {
+            pub const ALL: &'static [Action] = &[$(Action::$action,)*];
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Action::$action => $name,)*
+                }
+            }
+
+            pub fn description(self) -> &'static str {
+                match self {
+                    $(Action::$action => $description,)*
+                }
+            }
+        }
+    };
+}
+
+actions! {
+    Quit => "quit", "Quit the editor";
+    Open => "open", "Open a file in a new buffer";
+    FindFile => "find-file", "Find a file in the project by name";
+    SearchProject => "search-project", "Search the files in the project for text";
+    ReplaceProject => "replace-project", "Replace text in the files in the project";
+    Save => "save", "Save the current buffer";
+    SaveAs => "saveas", "Save the current buffer under another name";
+    Reload => "reload", "Reload the current buffer from its file";
+    ToggleFollow => "toggle-follow", "Follow text appended to the file, like tail -f";
+    Undo => "undo", "Undo the last edit";
+    Redo => "redo", "Redo the last undone edit";
+    GoToLine => "goto", "Go to a line and column";
+    BufferPicker => "buffers", "Pick a buffer from a list";
+    CloseBuffer => "close-buffer", "Close the current buffer";
+    NextBuffer => "next-buffer", "Switch to the next buffer";
+    PreviousBuffer => "previous-buffer", "Switch to the previous buffer";
+    SplitHorizontal => "split-horizontal", "Split the window into stacked windows";
+    SplitVertical => "split-vertical", "Split the window into side-by-side windows";
+    CloseWindow => "close-window", "Close the current window";
+    FocusUp => "focus-up", "Focus the window above";
+    FocusDown => "focus-down", "Focus the window below";
+    FocusLeft => "focus-left", "Focus the window to the left";
+    FocusRight => "focus-right", "Focus the window to the right";
+    ShrinkHeight => "shrink-height", "Make the window shorter";
+    GrowHeight => "grow-height", "Make the window taller";
+    ShrinkWidth => "shrink-width", "Make the window narrower";
+    GrowWidth => "grow-width", "Make the window wider";
+    ToggleTabBar => "toggle-tab-bar", "Show or hide the tab bar";
+    ToggleSoftWrap => "toggle-soft-wrap", "Turn soft line wrapping on or off";
+    ReflowParagraph => "reflow-paragraph", "Rewrap the current paragraph";
+    MoveUp => "move-up", "Move the cursor up";
+    MoveDown => "move-down", "Move the cursor down";
+    MoveLeft => "move-left", "Move the cursor left";
+    MoveRight => "move-right", "Move the cursor right";
+    MoveWordLeft => "move-word-left", "Move to the previous word";
+    MoveWordRight => "move-word-right", "Move to the next word";
+    LineStart => "line-start", "Move to the start of the line";
+    LineEnd => "line-end", "Move to the end of the line";
+    PageUp => "page-up", "Move up one screen";
+    PageDown => "page-down", "Move down one screen";
+    DocumentStart => "document-start", "Move to the start of the document";
+    DocumentEnd => "document-end", "Move to the end of the document";
+    InsertNewline => "insert-newline", "Split the line at the cursor";
+    DeleteBackward => "delete-backward", "Delete the character before the cursor";
+    DeleteForward => "delete-forward", "Delete the character under the cursor";
+    DeleteWordLeft => "delete-word-left", "Delete the word before the cursor";
+    DeleteWordRight => "delete-word-right", "Delete the word after the cursor";
+    DescribeKey => "describe-key", "Show which action a key runs";
+    SelectTheme => "select-theme", "Switch to another color theme";
+    SetMark => "set-mark", "Start a region at the cursor";
+    Cancel => "cancel", "Clear the mark";
+    KillLine => "kill-line", "Cut to the end of the line";
+    KillRegion => "kill-region", "Cut the region";
+    CopyRegion => "copy-region", "Copy the region";
+    Yank => "yank", "Paste the last cut or copied text";
+    YankPop => "yank-pop", "Replace the pasted text with an older kill";
+    CommandPalette => "command-palette", "Run a command by name";
+    Set => "set", "Change a setting until the editor quits";
+}
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|action| action.name() == name)
+    }
+
+    /// Actions that move between lines and keep the desired column.
+    pub fn is_vertical(self) -> bool {
+        matches!(
+            self,
+            Action::MoveUp | Action::MoveDown | Action::PageUp | Action::PageDown
+        )
+    }
+
+    /// What to type after the name of actions that take an argument in the
+    /// command palette.
+    pub fn usage(self) -> Option<&'static str> {
+        match self {
+            Action::Open | Action::SaveAs => Some("<file>"),
+            Action::GoToLine => Some("<line[:col]>"),
+            Action::SelectTheme => Some("<theme>"),
+            Action::Set => Some("<setting> <value>"),
+            Action::SearchProject => Some("<text>"),
+            _ => None,
+        }
+    }
+
+    /// Actions that only change the text, and so do nothing in a read-only
+    /// buffer.
+    pub fn edits_text(self) -> bool {
+        matches!(
+            self,
+            Action::InsertNewline | Action::DeleteBackward | Action::DeleteForward
+        )
+    }
+
+    /// Actions that add to the last kill when they follow another one.
+    pub fn is_kill(self) -> bool {
+        matches!(
+            self,
+            Action::KillLine | Action::KillRegion | Action::CopyRegion
+        )
+    }
+
+    /// Actions that leave the mark, and so the region, in place.
+    pub fn keeps_mark(self) -> bool {
+        matches!(
+            self,
+            Action::SetMark
+                | Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::MoveWordLeft
+                | Action::MoveWordRight
+                | Action::LineStart
+                | Action::LineEnd
+                | Action::PageUp
+                | Action::PageDown
+                | Action::DocumentStart
+                | Action::DocumentEnd
+                | Action::DescribeKey
+        )
+    }
+}
+
+const DEFAULT_BINDINGS: &[(&str, Action)] = &[
+    ("Ctrl-Q", Action::Quit),
+    ("Ctrl-O", Action::Open),
+    ("Ctrl-T", Action::FindFile),
+    ("Ctrl-F", Action::SearchProject),
+    ("Ctrl-R", Action::ReplaceProject),
+    ("Ctrl-S", Action::Save),
+    ("Ctrl-Z", Action::Undo),
+    ("Ctrl-Y", Action::Redo),
+    ("Ctrl-G", Action::GoToLine),
+    ("Ctrl-B", Action::BufferPicker),
+    ("Ctrl-W", Action::CloseBuffer),
+    ("Ctrl-PageDown", Action::NextBuffer),
+    ("Ctrl-PageUp", Action::PreviousBuffer),
+    ("Alt-S", Action::SplitHorizontal),
+    ("Alt-V", Action::SplitVertical),
+    ("Alt-W", Action::CloseWindow),
+    ("Alt-Up", Action::FocusUp),
+    ("Alt-Down", Action::FocusDown),
+    ("Alt-Left", Action::FocusLeft),
+    ("Alt-Right", Action::FocusRight),
+    ("Alt-Shift-Up", Action::ShrinkHeight),
+    ("Alt-Shift-Down", Action::GrowHeight),
+    ("Alt-Shift-Left", Action::ShrinkWidth),
+    ("Alt-Shift-Right", Action::GrowWidth),
+    ("Alt-T", Action::ToggleTabBar),
+    ("Alt-Z", Action::ToggleSoftWrap),
+    ("Alt-F", Action::ToggleFollow),
+    ("Alt-Q", Action::ReflowParagraph),
+    ("Up", Action::MoveUp),
+    ("Down", Action::MoveDown),
+    ("Left", Action::MoveLeft),
+    ("Right", Action::MoveRight),
+    ("Ctrl-Left", Action::MoveWordLeft),
+    ("Ctrl-Right", Action::MoveWordRight),
+    ("Home", Action::LineStart),
+    ("End", Action::LineEnd),
+    ("PageUp", Action::PageUp),
+    ("PageDown", Action::PageDown),
+    ("Ctrl-Home", Action::DocumentStart),
+    ("Ctrl-End", Action::DocumentEnd),
+    ("Enter", Action::InsertNewline),
+    ("Backspace", Action::DeleteBackward),
+    ("Delete", Action::DeleteForward),
+    ("Ctrl-Backspace", Action::DeleteWordLeft),
+    ("Alt-Backspace", Action::DeleteWordLeft),
+    ("Ctrl-H", Action::DeleteWordLeft),
+    ("Ctrl-Delete", Action::DeleteWordRight),
+    ("Alt-D", Action::DeleteWordRight),
+    ("F1", Action::DescribeKey),
+    ("Ctrl-K Ctrl-T", Action::SelectTheme),
+    ("Ctrl-P", Action::CommandPalette),
+];
+
+/// Bindings the Emacs profile adds on top of the default ones.
+const EMACS_BINDINGS: &[(&str, Action)] = &[
+    ("Ctrl-A", Action::LineStart),
+    ("Ctrl-E", Action::LineEnd),
+    ("Ctrl-F", Action::MoveRight),
+    ("Ctrl-B", Action::MoveLeft),
+    ("Ctrl-N", Action::MoveDown),
+    ("Ctrl-P", Action::MoveUp),
+    ("Alt-F", Action::MoveWordRight),
+    ("Alt-B", Action::MoveWordLeft),
+    ("Ctrl-V", Action::PageDown),
+    ("Alt-V", Action::PageUp),
+    ("Alt-<", Action::DocumentStart),
+    ("Alt->", Action::DocumentEnd),
+    ("Ctrl-D", Action::DeleteForward),
+    ("Ctrl-Space", Action::SetMark),
+    ("Ctrl-G", Action::Cancel),
+    ("Ctrl-K", Action::KillLine),
+    ("Ctrl-W", Action::KillRegion),
+    ("Alt-W", Action::CopyRegion),
+    ("Ctrl-Y", Action::Yank),
+    ("Alt-Y", Action::YankPop),
+    ("Ctrl-/", Action::Undo),
+    ("Ctrl-_", Action::Undo),
+    // What most terminals send for Ctrl-/ and Ctrl-_.
+    ("Ctrl-7", Action::Undo),
+    ("Alt-_", Action::Redo),
+    ("Alt-G g", Action::GoToLine),
+    ("Alt-G Alt-G", Action::GoToLine),
+    ("Alt-Q", Action::ReflowParagraph),
+    ("Ctrl-H k", Action::DescribeKey),
+    ("Alt-X", Action::CommandPalette),
+    ("Ctrl-X Ctrl-F", Action::Open),
+    ("Ctrl-X p f", Action::FindFile),
+    ("Ctrl-X p g", Action::SearchProject),
+    ("Ctrl-X p r", Action::ReplaceProject),
+    ("Ctrl-X Ctrl-S", Action::Save),
+    ("Ctrl-X Ctrl-W", Action::SaveAs),
+    ("Ctrl-X Ctrl-C", Action::Quit),
+    ("Ctrl-X u", Action::Undo),
+    ("Ctrl-X b", Action::BufferPicker),
+    ("Ctrl-X k", Action::CloseBuffer),
+    ("Ctrl-X Right", Action::NextBuffer),
+    ("Ctrl-X Left", Action::PreviousBuffer),
+    ("Ctrl-X 2", Action::SplitHorizontal),
+    ("Ctrl-X 3", Action::SplitVertical),
+    ("Ctrl-X 0", Action::CloseWindow),
+];
+
+/// Sets of key bindings the editor can start with.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Profile {
+    Default,
+    /// Modal editing on top of the keymap, see `vim.rs`.
+    Vim,
+    Emacs,
+}
+
+impl Profile {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "default" => Ok(Self::Default),
+            "vim" => Ok(Self::Vim),
+            "emacs" => Ok(Self::Emacs),
+            _ => Err("must be \"default\", \"vim\" or \"emacs\"".to_string()),
+        }
+    }
+}
+
+/// A single key press together with its modifiers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        // Shift is already part of a typed character, and terminals report
+        // Ctrl and Alt letters in either case.
+        match code {
+            KeyCode::Char(c) if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                Self {
+                    code: KeyCode::Char(c.to_ascii_lowercase()),
+                    modifiers: modifiers - KeyModifiers::SHIFT,
+                }
+            }
+            KeyCode::Char(_) => Self {
+                code,
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            _ => Self { code, modifiers },
+        }
+    }
+
+    /// The character typed by this chord, if it inserts text. Terminals
+    /// report characters typed with AltGr as Ctrl+Alt ones.
+    pub fn text(self) -> Option<char> {
+        let alt_gr = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        match self.code {
+            KeyCode::Char(c) if self.modifiers.is_empty() || self.modifiers == alt_gr => Some(c),
+            KeyCode::Tab if self.modifiers.is_empty() => Some('\t'),
+            _ => None,
+        }
+    }
+
+    /// Parses a chord such as `Ctrl-K`, `Alt-Shift-Up` or `F5`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = text;
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            let (modifier, len) = if lower.starts_with("ctrl-") && rest.len() > 5 {
+                (KeyModifiers::CONTROL, 5)
+            } else if lower.starts_with("alt-") && rest.len() > 4 {
+                (KeyModifiers::ALT, 4)
+            } else if lower.starts_with("shift-") && rest.len() > 6 {
+                (KeyModifiers::SHIFT, 6)
+            } else {
+                break;
+            };
+            modifiers |= modifier;
+            rest = &rest[len..];
+        }
+        let code = match rest.to_ascii_lowercase().as_str() {
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "esc" => KeyCode::Esc,
+            "space" => KeyCode::Char(' '),
+            name => match (name.strip_prefix('f'), rest.chars().count()) {
+                (Some(number), _) if number.parse::<u8>().is_ok_and(|n| (1..=12).contains(&n)) => {
+                    KeyCode::F(number.parse().unwrap_or(1))
+                }
+                (_, 1) => KeyCode::Char(rest.chars().next().unwrap_or(' ')),
+                _ => return Err(format!("unknown key `{text}`")),
+            },
+        };
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+impl From<KeyEvent> for KeyChord {
+    fn from(event: KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) if !self.modifiers.is_empty() => {
+                write!(f, "{}", c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) => write!(f, "{c}"),
+            KeyCode::F(number) => write!(f, "F{number}"),
+            KeyCode::PageUp => write!(f, "PageUp"),
+            KeyCode::PageDown => write!(f, "PageDown"),
+            KeyCode::BackTab => write!(f, "BackTab"),
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+/// Parses a space-separated key sequence such as `Ctrl-K Ctrl-C`.
+pub fn parse_sequence(text: &str) -> Result<Vec<KeyChord>, String> {
+    let chords = text
+        .split_whitespace()
+        .map(KeyChord::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if chords.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+    Ok(chords)
+}
+
+pub fn format_sequence(keys: &[KeyChord]) -> String {
+    keys.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub enum Lookup {
+    Action(Action),
+    /// The keys so far start one or more longer sequences.
+    Prefix,
+    Unbound,
+}
+
+/// Maps key sequences to actions.
+pub struct Keymap {
+    bindings: HashMap<Vec<KeyChord>, Action>,
+}
+
+impl Keymap {
+    /// The default bindings, with those of `profile` on top.
+    pub fn new(profile: Profile) -> Self {
+        let extra = match profile {
+            Profile::Emacs => EMACS_BINDINGS,
+            Profile::Default | Profile::Vim => &[],
+        };
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+        };
+        for &(keys, action) in DEFAULT_BINDINGS.iter().chain(extra) {
+            let keys = parse_sequence(keys).expect("built-in key bindings are valid");
+            keymap.bind(keys, Some(action));
+        }
+        keymap
+    }
+
+    /// Binds `keys` to `action`, or removes the binding if `action` is None.
+    /// Bindings that `keys` is a prefix of, or that are a prefix of `keys`,
+    /// are removed as they could never run.
+    pub fn bind(&mut self, keys: Vec<KeyChord>, action: Option<Action>) {
+        match action {
+            Some(action) => {
+                self.bindings
+                    .retain(|bound, _| !bound.starts_with(&keys) && !keys.starts_with(bound));
+                self.bindings.insert(keys, action);
+            }
+            None => {
+                self.bindings.remove(&keys);
+            }
+        }
+    }
+
+    pub fn lookup(&self, keys: &[KeyChord]) -> Lookup {
+        if let Some(action) = self.bindings.get(keys) {
+            return Lookup::Action(*action);
+        }
+        let is_prefix = self
+            .bindings
+            .keys()
+            .any(|bound| bound.len() > keys.len() && bound.starts_with(keys));
+        if is_prefix {
+            Lookup::Prefix
+        } else {
+            Lookup::Unbound
+        }
+    }
+
+    /// Key sequences bound to `action`, shortest first.
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        let mut keys: Vec<&Vec<KeyChord>> = self
+            .bindings
+            .iter()
+            .filter(|(_, bound)| **bound == action)
+            .map(|(keys, _)| keys)
+            .collect();
+        keys.sort_by_key(|keys| (keys.len(), format_sequence(keys)));
+        keys.into_iter().map(|keys| format_sequence(keys)).collect()
+    }
+}
@@ -0,0 +1,41 @@
+/// How many kills are kept before the oldest is dropped.
+const KILL_RING_SIZE: usize = 60;
+
+/// Text removed or copied by the kill actions, newest last.
+#[derive(Default)]
+pub struct KillRing {
+    kills: Vec<String>,
+    /// How many kills back the last yank went.
+    yank_depth: usize,
+}
+
+impl KillRing {
+    /// Adds `text` as a new kill, or to the end of the newest one if `append`.
+    pub fn kill(&mut self, text: String, append: bool) {
+        match self.kills.last_mut() {
+            Some(last) if append => last.push_str(&text),
+            _ => {
+                self.kills.push(text);
+                if self.kills.len() > KILL_RING_SIZE {
+                    self.kills.remove(0);
+                }
+            }
+        }
+    }
+
+    /// The newest kill.
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank_depth = 0;
+        self.kills.last().map(String::as_str)
+    }
+
+    /// The kill before the one yanked last, wrapping around to the newest.
+    pub fn rotate(&mut self) -> Option<&str> {
+        if self.kills.is_empty() {
+            return None;
+        }
+        self.yank_depth = (self.yank_depth + 1) % self.kills.len();
+        let index = self.kills.len() - 1 - self.yank_depth;
+        Some(&self.kills[index])
+    }
+}
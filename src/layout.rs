@@ -0,0 +1,246 @@
+const MIN_RATIO: u16 = 10;
+const MAX_RATIO: u16 = 90;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Split {
+    /// Windows stacked on top of each other.
+    Horizontal,
+    /// Windows side by side.
+    Vertical,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+enum Node {
+    Window(usize),
+    Split {
+        split: Split,
+        /// Share of the space given to `first`, in percent.
+        ratio: u16,
+        first: Box<Node>,
+        second: Box<Node>,
+    },
+}
+
+/// Arrangement of windows on the screen, as a tree of splits whose leaves
+/// are window indices.
+pub struct Layout {
+    root: Node,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            root: Node::Window(0),
+        }
+    }
+}
+
+impl Layout {
+    /// Splits `window` in two, placing `new_window` below or to the right.
+    pub fn split(&mut self, window: usize, split: Split, new_window: usize) {
+        if let Some(node) = self.root.find_mut(window) {
+            let old = std::mem::replace(node, Node::Window(new_window));
+            *node = Node::Split {
+                split,
+                ratio: 50,
+                first: Box::new(old),
+                second: Box::new(Node::Window(new_window)),
+            };
+        }
+    }
+
+    /// Removes `window`, giving its space to its sibling. Windows with a
+    /// higher index are renumbered down by one. Returns false if `window` is
+    /// the last one.
+    pub fn close(&mut self, window: usize) -> bool {
+        if matches!(self.root, Node::Window(_)) || !self.root.remove(window) {
+            return false;
+        }
+        self.root.renumber(window);
+        true
+    }
+
+    /// Grows `window` by `delta` percent along the closest enclosing split of
+    /// the given kind. Negative values shrink it.
+    pub fn resize(&mut self, window: usize, split: Split, delta: i16) {
+        self.root.resize(window, split, delta);
+    }
+
+    /// Positions of every window, and of the one-column separators between
+    /// side-by-side windows, inside `area`.
+    pub fn arrange(&self, area: Rect) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let mut windows = Vec::new();
+        let mut separators = Vec::new();
+        self.root.arrange(area, &mut windows, &mut separators);
+        (windows, separators)
+    }
+
+    /// The window next to `window` in `direction`, preferring the one that
+    /// overlaps it the most.
+    pub fn neighbor(&self, area: Rect, window: usize, direction: Direction) -> Option<usize> {
+        let (windows, _) = self.arrange(area);
+        let (_, from) = windows.iter().find(|(index, _)| *index == window)?;
+        let overlap = |a: usize, a_len: usize, b: usize, b_len: usize| {
+            (a + a_len).min(b + b_len).saturating_sub(a.max(b))
+        };
+        windows
+            .iter()
+            .filter(|(index, _)| *index != window)
+            .filter_map(|(index, to)| {
+                let adjacent = match direction {
+                    Direction::Up => to.y + to.height <= from.y,
+                    Direction::Down => to.y >= from.y + from.height,
+                    Direction::Left => to.x + to.width <= from.x,
+                    Direction::Right => to.x >= from.x + from.width,
+                };
+                let shared = match direction {
+                    Direction::Up | Direction::Down => overlap(from.x, from.width, to.x, to.width),
+                    Direction::Left | Direction::Right => {
+                        overlap(from.y, from.height, to.y, to.height)
+                    }
+                };
+                let distance = from.x.abs_diff(to.x) + from.y.abs_diff(to.y);
+                (adjacent && shared > 0).then_some((*index, distance, shared))
+            })
+            .min_by_key(|&(_, distance, shared)| (distance, usize::MAX - shared))
+            .map(|(index, _, _)| index)
+    }
+}
+
+impl Node {
+    fn find_mut(&mut self, window: usize) -> Option<&mut Node> {
+        match self {
+            Node::Window(index) if *index == window => Some(self),
+            Node::Window(_) => None,
+            Node::Split { first, second, .. } => {
+                first.find_mut(window).or_else(|| second.find_mut(window))
+            }
+        }
+    }
+
+    fn contains(&self, window: usize) -> bool {
+        match self {
+            Node::Window(index) => *index == window,
+            Node::Split { first, second, .. } => first.contains(window) || second.contains(window),
+        }
+    }
+
+    fn remove(&mut self, window: usize) -> bool {
+        let Node::Split { first, second, .. } = self else {
+            return false;
+        };
+        let survivor = if matches!(**first, Node::Window(index) if index == window) {
+            std::mem::replace(&mut **second, Node::Window(0))
+        } else if matches!(**second, Node::Window(index) if index == window) {
+            std::mem::replace(&mut **first, Node::Window(0))
+        } else {
+            return first.remove(window) || second.remove(window);
+        };
+        *self = survivor;
+        true
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Node::Window(index) if *index > removed => *index -= 1,
+            Node::Window(_) => (),
+            Node::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    fn resize(&mut self, window: usize, kind: Split, delta: i16) -> bool {
+        let Node::Split {
+            split,
+            ratio,
+            first,
+            second,
+        } = self
+        else {
+            return false;
+        };
+        let in_first = first.contains(window);
+        if !in_first && !second.contains(window) {
+            return false;
+        }
+        let child = if in_first { first } else { second };
+        if child.resize(window, kind, delta) {
+            return true;
+        }
+        if *split != kind {
+            return false;
+        }
+        let delta = if in_first { delta } else { -delta };
+        *ratio = ratio
+            .saturating_add_signed(delta)
+            .clamp(MIN_RATIO, MAX_RATIO);
+        true
+    }
+
+    fn arrange(&self, area: Rect, windows: &mut Vec<(usize, Rect)>, separators: &mut Vec<Rect>) {
+        match self {
+            Node::Window(index) => windows.push((*index, area)),
+            Node::Split {
+                split: Split::Horizontal,
+                ratio,
+                first,
+                second,
+            } => {
+                let top = area.height * *ratio as usize / 100;
+                let top_area = Rect {
+                    height: top,
+                    ..area
+                };
+                let bottom_area = Rect {
+                    y: area.y + top,
+                    height: area.height - top,
+                    ..area
+                };
+                first.arrange(top_area, windows, separators);
+                second.arrange(bottom_area, windows, separators);
+            }
+            Node::Split {
+                split: Split::Vertical,
+                ratio,
+                first,
+                second,
+            } => {
+                let left = area.width.saturating_sub(1) * *ratio as usize / 100;
+                let left_area = Rect {
+                    width: left,
+                    ..area
+                };
+                let separator = Rect {
+                    x: area.x + left,
+                    width: 1.min(area.width),
+                    ..area
+                };
+                let right_area = Rect {
+                    x: separator.x + separator.width,
+                    width: area.width.saturating_sub(left + separator.width),
+                    ..area
+                };
+                first.arrange(left_area, windows, separators);
+                separators.push(separator);
+                second.arrange(right_area, windows, separators);
+            }
+        }
+    }
+}
@@ -1,7 +1,21 @@
+mod color;
+mod config;
+mod diff;
 mod document;
 mod editor;
+mod explorer;
+mod finder;
+mod fuzzy;
+mod input;
+mod keymap;
+mod kill_ring;
+mod layout;
+mod path;
 mod row;
+mod search;
 mod terminal;
+mod theme;
+mod vim;
 
 pub use document::Document;
 use editor::Editor;
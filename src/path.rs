@@ -0,0 +1,44 @@
+use std::{env, fs, path::PathBuf};
+
+/// Replaces a leading `~` with the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), env::var_os("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Completions of a partly typed path: the entries of its directory that
+/// start with its last component, with a `/` after directories. Hidden
+/// entries are only offered once their leading `.` has been typed.
+pub fn complete(text: &str) -> Vec<String> {
+    if text == "~" {
+        return vec!["~/".to_string()];
+    }
+    let (dir, prefix) = match text.rfind('/') {
+        Some(index) => text.split_at(index + 1),
+        None => ("", text),
+    };
+    let dir_path = match dir {
+        "" => PathBuf::from("."),
+        dir => expand_home(dir),
+    };
+    let Ok(entries) = fs::read_dir(dir_path) else {
+        return Vec::new();
+    };
+    let mut completions: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{name}{slash}"))
+        })
+        .collect();
+    completions.sort();
+    completions
+}
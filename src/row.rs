@@ -1,31 +1,62 @@
 use std::cmp;
 use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Row {
     string: String,
     len: usize,
 }
 
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
+    pub fn render(&self, start: usize, end: usize, tab_width: usize) -> String {
         let end = cmp::min(end, self.string.len());
         let start = cmp::min(start, end);
         let mut result = String::new();
+        let mut column = self.column(start, tab_width);
         for grapheme in self.string[..]
             .graphemes(true)
             .skip(start)
             .take(end - start)
         {
+            let next = advance(column, grapheme, tab_width);
             if grapheme == "\t" {
-                result.push_str(" ");
+                result.push_str(&" ".repeat(next - column));
             } else {
                 result.push_str(grapheme);
             }
+            column = next;
         }
         result
     }
 
+    /// Screen column at which grapheme `at` starts, with tabs advancing to
+    /// the next multiple of `tab_width`.
+    pub fn column(&self, at: usize, tab_width: usize) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .take(at)
+            .fold(0, |column, grapheme| advance(column, grapheme, tab_width))
+    }
+
+    /// Screen columns of every grapheme boundary, from 0 up to the row length.
+    pub fn columns(&self, tab_width: usize) -> Vec<usize> {
+        let mut columns = vec![0];
+        let mut column = 0;
+        for grapheme in self.string[..].graphemes(true) {
+            column = advance(column, grapheme, tab_width);
+            columns.push(column);
+        }
+        columns
+    }
+
+    /// The last grapheme index starting at or before screen `column`.
+    pub fn index_at_column(&self, column: usize, tab_width: usize) -> usize {
+        self.columns(tab_width)
+            .iter()
+            .rposition(|&start| start <= column)
+            .unwrap_or(0)
+    }
+
     pub fn len(&self) -> usize {
         self.len
     }
@@ -76,7 +107,6 @@ impl Row {
     }
 
     pub fn split(&mut self, at: usize) -> Self {
-
         let mut result: String = String::new();
         let mut length = 0;
         let mut split_result: String = String::new();
@@ -99,9 +129,91 @@ impl Row {
         }
     }
 
+    /// Splits the row into screen lines at most `width` columns wide, breaking
+    /// after whitespace where possible. Returns `(start, end)` grapheme ranges.
+    /// A row whose last line is full ends with an empty line, where the
+    /// cursor goes at the end of the row.
+    pub fn wrap(&self, width: usize, tab_width: usize) -> Vec<(usize, usize)> {
+        let width = cmp::max(width, 1);
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let columns = self.columns(tab_width);
+        let mut segments = Vec::new();
+        let mut start = 0;
+        while columns[self.len] - columns[start] > width {
+            let limit = (start + 1..=self.len)
+                .take_while(|&end| columns[end] - columns[start] <= width)
+                .last()
+                .unwrap_or(start + 1);
+            let end = (start + 1..=limit)
+                .rev()
+                .find(|&index| graphemes[index - 1].trim().is_empty())
+                .unwrap_or(limit);
+            segments.push((start, end));
+            start = end;
+        }
+        segments.push((start, self.len));
+        if self.len > start && columns[self.len] - columns[start] == width {
+            segments.push((self.len, self.len));
+        }
+        segments
+    }
+
+    /// Grapheme index of the start of the word before `at`, or 0.
+    pub fn previous_word_start(&self, at: usize) -> usize {
+        self.words()
+            .filter(|&(start, _)| start < at)
+            .last()
+            .map_or(0, |(start, _)| start)
+    }
+
+    /// Grapheme index of the end of the word after `at`, or the row length.
+    pub fn next_word_end(&self, at: usize) -> usize {
+        self.words()
+            .find(|&(_, end)| end > at)
+            .map_or(self.len, |(_, end)| end)
+    }
+
+    /// Grapheme ranges of the words in the row, using Unicode word boundaries
+    /// and skipping whitespace and punctuation.
+    fn words(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut index = 0;
+        self.string[..].split_word_bounds().filter_map(move |word| {
+            let start = index;
+            index += word.graphemes(true).count();
+            word.chars()
+                .any(char::is_alphanumeric)
+                .then_some((start, index))
+        })
+    }
+
+    /// The text of graphemes `start..end`.
+    pub fn slice(&self, start: usize, end: usize) -> &str {
+        let byte = |index: usize| {
+            self.string[..]
+                .grapheme_indices(true)
+                .nth(index)
+                .map_or(self.string.len(), |(byte, _)| byte)
+        };
+        let end = byte(end);
+        &self.string[byte(start).min(end)..end]
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         self.string.as_bytes()
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+}
+
+fn advance(column: usize, grapheme: &str, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_width = cmp::max(tab_width, 1);
+        column + tab_width - column % tab_width
+    } else {
+        column + 1
+    }
 }
 
 impl From<&str> for Row {
@@ -112,3 +224,47 @@ impl From<&str> for Row {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrap(text: &str, width: usize) -> Vec<(usize, usize)> {
+        Row::from(text).wrap(width, 4)
+    }
+
+    #[test]
+    fn wrap_short_rows() {
+        assert_eq!(wrap("", 10), [(0, 0)]);
+        assert_eq!(wrap("hello", 10), [(0, 5)]);
+    }
+
+    #[test]
+    fn wrap_after_whitespace() {
+        assert_eq!(wrap("hello big world", 10), [(0, 10), (10, 15)]);
+        assert_eq!(wrap("one two three", 8), [(0, 8), (8, 13)]);
+    }
+
+    #[test]
+    fn wrap_splits_long_words() {
+        assert_eq!(wrap("abcdefghij", 4), [(0, 4), (4, 8), (8, 10)]);
+        assert_eq!(wrap("ab abcdefgh", 4), [(0, 3), (3, 7), (7, 11), (11, 11)]);
+    }
+
+    #[test]
+    fn wrap_leaves_room_for_the_cursor() {
+        assert_eq!(wrap("abcd", 4), [(0, 4), (4, 4)]);
+        assert_eq!(wrap("ab cd", 3), [(0, 3), (3, 5)]);
+    }
+
+    #[test]
+    fn wrap_counts_tab_columns() {
+        assert_eq!(wrap("\tab", 5), [(0, 1), (1, 3)]);
+        assert_eq!(wrap("ab\tcd", 5), [(0, 3), (3, 5)]);
+    }
+
+    #[test]
+    fn wrap_width_zero() {
+        assert_eq!(wrap("abc", 0), [(0, 1), (1, 2), (2, 3), (3, 3)]);
+    }
+}
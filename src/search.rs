@@ -0,0 +1,347 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::Path,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use ignore::WalkBuilder;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{Document, Position, Row};
+
+/// The search stops after this many hits so huge result sets don't bog the
+/// editor down.
+const MAX_HITS: usize = 10_000;
+
+/// An occurrence of the pattern. `column` and `len` count graphemes, as
+/// positions in a `Row` do.
+#[derive(Clone)]
+pub struct Hit {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+    pub text: String,
+}
+
+impl Hit {
+    /// The text the pattern matched.
+    pub fn matched(&self) -> String {
+        Row::from(self.text.as_str())
+            .slice(self.column, self.column + self.len)
+            .to_string()
+    }
+
+    /// The line with this hit replaced by `replacement`.
+    fn replaced(&self, replacement: &str) -> String {
+        let row = Row::from(self.text.as_str());
+        format!(
+            "{}{replacement}{}",
+            row.slice(0, self.column),
+            row.slice(self.column + self.len, row.len())
+        )
+    }
+}
+
+/// A search of the files under a directory for a literal pattern, run on a
+/// background thread so results come in while it is going. Files ignored by
+/// `.gitignore`, hidden files and binary files are skipped. The pattern
+/// matches case-insensitively unless it contains capitals.
+///
+/// With a replacement the results preview the changes instead, grouped by
+/// file, and each hit can be left out.
+pub struct ProjectSearch {
+    pub pattern: String,
+    pub replacement: Option<String>,
+    pub hits: Vec<Hit>,
+    /// Indices of the hits left out of the replacement.
+    excluded: HashSet<usize>,
+    /// Set once the replacements have been made.
+    pub applied: bool,
+    receiver: Receiver<Hit>,
+    done: bool,
+}
+
+impl ProjectSearch {
+    pub fn new(root: &Path, pattern: &str, replacement: Option<&str>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let root = root.to_path_buf();
+        let wanted = pattern.to_string();
+        thread::spawn(move || {
+            let walker = WalkBuilder::new(&root).require_git(false).build();
+            let mut count = 0;
+            for entry in walker.flatten() {
+                if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                    continue;
+                }
+                let Some(contents) = read_text(entry.path()) else {
+                    continue;
+                };
+                let path = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+                let path = path.to_string_lossy();
+                for (line, text) in contents.lines().enumerate() {
+                    for (column, len) in find(text, &wanted) {
+                        let hit = Hit {
+                            path: path.to_string(),
+                            line,
+                            column,
+                            len,
+                            text: text.to_string(),
+                        };
+                        // The receiver is gone once the results are closed.
+                        if sender.send(hit).is_err() {
+                            return;
+                        }
+                        count += 1;
+                        if count >= MAX_HITS {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        Self {
+            pattern: pattern.to_string(),
+            replacement: replacement.map(str::to_string),
+            hits: Vec::new(),
+            excluded: HashSet::new(),
+            applied: false,
+            receiver,
+            done: false,
+        }
+    }
+
+    /// Adds the hits found since the last call. Returns whether any were.
+    pub fn poll(&mut self) -> bool {
+        let before = self.hits.len();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(hit) => self.hits.push(hit),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        self.hits.len() > before
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// The name of the results buffer.
+    pub fn name(&self) -> String {
+        match self.replacement {
+            Some(_) => format!("Replace \"{}\"", self.pattern),
+            None => format!("Search \"{}\"", self.pattern),
+        }
+    }
+
+    /// The rows of the results buffer.
+    pub fn lines(&self) -> Vec<String> {
+        self.rows().into_iter().map(|(line, _)| line).collect()
+    }
+
+    /// The hit shown on row `y` of the results buffer.
+    pub fn hit(&self, y: usize) -> Option<&Hit> {
+        self.rows().get(y)?.1.map(|index| &self.hits[index])
+    }
+
+    /// The hits to replace, in the order they were found.
+    pub fn accepted(&self) -> impl Iterator<Item = &Hit> {
+        self.hits
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.excluded.contains(index))
+            .map(|(_, hit)| hit)
+    }
+
+    /// Includes or leaves out the hit on row `y`, or all hits of the file
+    /// when `y` is its heading.
+    pub fn toggle(&mut self, y: usize) {
+        let rows = self.rows();
+        let indices: Vec<usize> = match rows.get(y) {
+            Some((_, Some(index))) => vec![*index],
+            Some((_, None)) if y > 0 => rows[y + 1..]
+                .iter()
+                .map_while(|(_, index)| *index)
+                .collect(),
+            _ => return,
+        };
+        if indices.iter().any(|index| !self.excluded.contains(index)) {
+            self.excluded.extend(indices);
+        } else {
+            for index in indices {
+                self.excluded.remove(&index);
+            }
+        }
+    }
+
+    /// The rows of the results buffer with the index of the hit each shows:
+    /// a summary, then one `path:line:column` row per hit. Replacements are
+    /// grouped under a heading per file instead, each row showing the line
+    /// as it will be.
+    fn rows(&self) -> Vec<(String, Option<usize>)> {
+        let files = self
+            .hits
+            .windows(2)
+            .filter(|pair| pair[0].path != pair[1].path)
+            .count()
+            + usize::from(!self.hits.is_empty());
+        let state = match (self.done, self.hits.len() >= MAX_HITS) {
+            (false, _) => ", searching...",
+            (true, true) => ", stopped at the limit",
+            (true, false) => "",
+        };
+        let Some(replacement) = &self.replacement else {
+            let mut rows = vec![(
+                format!(
+                    "{} for \"{}\" in {}{state}",
+                    plural(self.hits.len(), "hit"),
+                    self.pattern,
+                    plural(files, "file")
+                ),
+                None,
+            )];
+            rows.extend(self.hits.iter().enumerate().map(|(index, hit)| {
+                let line = format!(
+                    "{}:{}:{}: {}",
+                    hit.path,
+                    hit.line + 1,
+                    hit.column + 1,
+                    hit.text.trim()
+                );
+                (line, Some(index))
+            }));
+            return rows;
+        };
+        let state = if self.applied { ", done" } else { state };
+        let mut rows = vec![(
+            format!(
+                "Replace \"{}\" with \"{replacement}\": {} of {} in {}{state}",
+                self.pattern,
+                self.accepted().count(),
+                plural(self.hits.len(), "hit"),
+                plural(files, "file")
+            ),
+            None,
+        )];
+        for (index, hit) in self.hits.iter().enumerate() {
+            if index == 0 || self.hits[index - 1].path != hit.path {
+                rows.push((hit.path.clone(), None));
+            }
+            let mark = if self.excluded.contains(&index) {
+                ' '
+            } else {
+                'x'
+            };
+            let line = format!(
+                "  [{mark}] {}:{}: {}",
+                hit.line + 1,
+                hit.column + 1,
+                hit.replaced(replacement).trim()
+            );
+            rows.push((line, Some(index)));
+        }
+        rows
+    }
+}
+
+pub fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {noun}"),
+        _ => format!("{count} {noun}s"),
+    }
+}
+
+/// Replaces `hits`, all in `document` and in the order found, by
+/// `replacement`, as one undo step. Returns how many hits still matched and
+/// were replaced.
+pub fn replace_in_document(document: &mut Document, hits: &[Hit], replacement: &str) -> usize {
+    let (Some(first), Some(last)) = (hits.first(), hits.last()) else {
+        return 0;
+    };
+    let mut lines = Vec::new();
+    let mut count = 0;
+    let mut hits = hits;
+    for y in first.line..=last.line {
+        let Some(row) = document.row(y) else {
+            break;
+        };
+        let split = hits
+            .iter()
+            .position(|hit| hit.line != y)
+            .unwrap_or(hits.len());
+        let (line_hits, rest) = hits.split_at(split);
+        hits = rest;
+        let (line, line_count) = replace_line(row.as_str(), line_hits, replacement);
+        lines.push(line);
+        count += line_count;
+    }
+    if count > 0 {
+        let end = first.line + lines.len() - 1;
+        let start = Position {
+            x: 0,
+            y: first.line,
+        };
+        let end = Position {
+            x: document.row(end).map_or(0, Row::len),
+            y: end,
+        };
+        document.replace(&start, &end, &lines.join("\n"));
+    }
+    count
+}
+
+/// `text` with `hits`, all on it and in order, replaced by `replacement`,
+/// and how many of them it still matched.
+fn replace_line(text: &str, hits: &[Hit], replacement: &str) -> (String, usize) {
+    let row = Row::from(text);
+    let mut result = String::new();
+    let mut count = 0;
+    let mut x = 0;
+    for hit in hits {
+        let end = hit.column + hit.len;
+        if hit.column < x || row.slice(hit.column, end) != hit.matched() {
+            continue;
+        }
+        result.push_str(row.slice(x, hit.column));
+        result.push_str(replacement);
+        x = end;
+        count += 1;
+    }
+    result.push_str(row.slice(x, row.len()));
+    (result, count)
+}
+
+/// The contents of the file at `path`, or None if it can't be read or is
+/// not text.
+fn read_text(path: &Path) -> Option<String> {
+    let contents = String::from_utf8(fs::read(path).ok()?).ok()?;
+    (!contents.contains('\0')).then_some(contents)
+}
+
+/// The grapheme column and length of each occurrence of `pattern` in `text`.
+fn find(text: &str, pattern: &str) -> Vec<(usize, usize)> {
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    // ASCII case folding keeps byte offsets the same in both strings.
+    let (haystack, needle) = if pattern.chars().any(char::is_uppercase) {
+        (text.to_string(), pattern.to_string())
+    } else {
+        (text.to_ascii_lowercase(), pattern.to_ascii_lowercase())
+    };
+    haystack
+        .match_indices(&needle)
+        .map(|(start, found)| {
+            let column = text[..start].graphemes(true).count();
+            let len = text[start..start + found.len()].graphemes(true).count();
+            (column, len)
+        })
+        .collect()
+}
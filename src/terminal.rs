@@ -1,25 +1,22 @@
-use std::io::{self, Error, Write};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Error, Write},
+    os::fd::{AsFd, AsRawFd},
+    time::{Duration, Instant},
+};
 
 use crossterm::{
     cursor,
-    event::{read, Event, KeyEvent},
-    style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor},
+    event::{
+        poll, read, DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture,
+        Event, KeyEvent,
+    },
+    style::{Attribute, Color, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor},
     terminal::{self, size},
     ExecutableCommand,
 };
 
-use crate::editor::Position;
-
-const EDITOR_BG_COLOR: Color = Color::Rgb {
-    r: 16,
-    g: 15,
-    b: 15,
-};
-const EDITOR_FG_COLOR: Color = Color::Rgb {
-    r: 206,
-    g: 205,
-    b: 195,
-};
+use crate::{color::ColorSupport, editor::Position, theme::Style};
 
 pub struct Size {
     pub width: u16,
@@ -28,22 +25,85 @@ pub struct Size {
 
 pub struct Terminal {
     size: Size,
+    background: Color,
+    foreground: Color,
+    color_support: ColorSupport,
+    mouse_capture: bool,
 }
 
 impl Terminal {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self, Error> {
         terminal::enable_raw_mode()?;
-        Terminal::reset_bg_color();
-        Terminal::reset_fg_color();
+        io::stdout().execute(EnableFocusChange)?;
         let (columns, rows) = size()?;
         Ok(Self {
             size: Size {
                 width: columns,
-                height: rows.saturating_sub(2),
+                height: rows,
             },
+            background: Color::Reset,
+            foreground: Color::Reset,
+            color_support: ColorSupport::TrueColor,
+            mouse_capture: false,
         })
     }
 
+    /// Sets the colors restored by `reset_style` and used where a style
+    /// leaves them unset.
+    pub fn set_default_style(&mut self, style: &Style) {
+        self.background = style.bg.unwrap_or(Color::Reset);
+        self.foreground = style.fg.unwrap_or(Color::Reset);
+        self.reset_style();
+    }
+
+    /// Sets how colors are mapped before they are written.
+    pub fn set_color_support(&mut self, color_support: ColorSupport) {
+        self.color_support = color_support;
+    }
+
+    pub fn color_support(&self) -> ColorSupport {
+        self.color_support
+    }
+
+    /// Turns mouse reporting on or off. While it is on, the terminal's own
+    /// text selection needs Shift held in most terminals.
+    pub fn set_mouse_capture(&mut self, enabled: bool) -> Result<(), Error> {
+        if enabled != self.mouse_capture {
+            if enabled {
+                io::stdout().execute(EnableMouseCapture)?;
+            } else {
+                io::stdout().execute(DisableMouseCapture)?;
+            }
+            self.mouse_capture = enabled;
+        }
+        Ok(())
+    }
+
+    /// Points standard output at the terminal, so the editor can be drawn
+    /// while it is redirected, and returns the original standard output.
+    pub fn redirect_stdout() -> Result<File, Error> {
+        let output = File::from(io::stdout().as_fd().try_clone_to_owned()?);
+        let tty = OpenOptions::new().write(true).open("/dev/tty")?;
+        // SAFETY: both are open descriptors, and replacing the process's
+        // standard output is the point.
+        if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDOUT_FILENO) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(output)
+    }
+
+    /// Undoes the terminal setup done by `default`.
+    pub fn restore() {
+        io::stdout()
+            .execute(DisableMouseCapture)
+            .expect("failed to disable mouse capture");
+        io::stdout()
+            .execute(DisableFocusChange)
+            .expect("failed to disable focus reporting");
+        terminal::disable_raw_mode().expect("failed to disable raw mode");
+    }
+
     pub fn size(&self) -> &Size {
         &self.size
     }
@@ -92,28 +152,63 @@ impl Terminal {
         }
     }
 
-    pub fn set_bg_color(color: Color) {
-        io::stdout()
-            .execute(SetBackgroundColor(color))
-            .expect("failed to set bg color");
+    /// Waits up to `timeout` for a key press, mouse event or the terminal
+    /// regaining focus.
+    pub fn poll_event(timeout: Duration) -> Result<Option<Event>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !poll(remaining)? {
+                return Ok(None);
+            }
+            if let event @ (Event::Key(_) | Event::Mouse(_) | Event::FocusGained) = read()? {
+                return Ok(Some(event));
+            }
+        }
     }
 
-    pub fn reset_bg_color() {
-        io::stdout()
-            .execute(SetBackgroundColor(EDITOR_BG_COLOR))
-            .expect("failed to reset bg color");
+    /// Waits for the next key press or mouse event.
+    pub fn read_event() -> Result<Event, Error> {
+        loop {
+            if let event @ (Event::Key(_) | Event::Mouse(_)) = read()? {
+                return Ok(event);
+            }
+        }
     }
 
-    pub fn set_fg_color(color: Color) {
-        io::stdout()
-            .execute(SetForegroundColor(color))
+    pub fn set_style(&self, style: &Style) {
+        let mut stdout = io::stdout();
+        stdout
+            .execute(SetAttribute(Attribute::Reset))
+            .expect("failed to reset attributes");
+        stdout
+            .execute(SetBackgroundColor(
+                self.color_support.map(style.bg.unwrap_or(self.background)),
+            ))
+            .expect("failed to set bg color");
+        stdout
+            .execute(SetForegroundColor(
+                self.color_support.map(style.fg.unwrap_or(self.foreground)),
+            ))
             .expect("failed to set fg color");
+        let attributes = [
+            (style.bold, Attribute::Bold),
+            (style.italic, Attribute::Italic),
+            (style.underline, Attribute::Underlined),
+            (style.reverse, Attribute::Reverse),
+        ];
+        for (enabled, attribute) in attributes {
+            if enabled == Some(true) {
+                stdout
+                    .execute(SetAttribute(attribute))
+                    .expect("failed to set attribute");
+            }
+        }
     }
 
-    pub fn reset_fg_color() {
-        io::stdout()
-            .execute(SetBackgroundColor(EDITOR_FG_COLOR))
-            .expect("failed to reset fg color");
+    /// Goes back to the default colors, without attributes.
+    pub fn reset_style(&self) {
+        self.set_style(&Style::default());
     }
 
     pub fn reset_color() {
@@ -0,0 +1,220 @@
+use std::{collections::HashMap, fs};
+
+use crossterm::style::Color;
+use toml::{Table, Value};
+
+use crate::config::config_dir;
+
+/// Styles a theme can set. A dotted name falls back to its prefix, and every
+/// style falls back to `text`.
+pub const STYLE_NAMES: &[&str] = &[
+    "text",
+    "gutter",
+    "status",
+    "message",
+    "separator",
+    "title",
+    "title.active",
+    "tab",
+    "tab.active",
+    "popup",
+    "popup.selected",
+    "selection",
+    "search",
+    "syntax.keyword",
+    "syntax.type",
+    "syntax.function",
+    "syntax.string",
+    "syntax.number",
+    "syntax.comment",
+];
+
+const BUNDLED: &[(&str, &str)] = &[
+    ("dark", include_str!("../themes/dark.toml")),
+    ("light", include_str!("../themes/light.toml")),
+    ("terminal", include_str!("../themes/terminal.toml")),
+];
+
+/// How deep `inherits` chains may go, to stop cycles.
+const MAX_INHERITS: usize = 8;
+
+/// Colors and attributes; unset fields are inherited.
+#[derive(Default, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+    pub reverse: Option<bool>,
+}
+
+impl Style {
+    /// `self` with the fields set in `other` replaced.
+    fn patch(self, other: Self) -> Self {
+        Self {
+            fg: other.fg.or(self.fg),
+            bg: other.bg.or(self.bg),
+            bold: other.bold.or(self.bold),
+            italic: other.italic.or(self.italic),
+            underline: other.underline.or(self.underline),
+            reverse: other.reverse.or(self.reverse),
+        }
+    }
+
+    /// Parses a table like `{ fg = "#cecdc3", bold = true }`.
+    pub fn parse(value: &Value) -> Result<Self, String> {
+        let table = value.as_table().ok_or("must be a table")?;
+        let mut style = Self::default();
+        for (key, value) in table {
+            let flag = || {
+                value
+                    .as_bool()
+                    .ok_or(format!("`{key}` must be true or false"))
+            };
+            match key.as_str() {
+                "fg" => style.fg = Some(parse_color(value)?),
+                "bg" => style.bg = Some(parse_color(value)?),
+                "bold" => style.bold = Some(flag()?),
+                "italic" => style.italic = Some(flag()?),
+                "underline" => style.underline = Some(flag()?),
+                "reverse" => style.reverse = Some(flag()?),
+                _ => return Err(format!("`{key}` is not a style property")),
+            }
+        }
+        Ok(style)
+    }
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    styles: HashMap<String, Style>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::load("dark").expect("bundled themes are valid")
+    }
+}
+
+impl Theme {
+    /// Loads `name` from the `themes` directory in the config directory, or
+    /// from the bundled themes.
+    pub fn load(name: &str) -> Result<Self, String> {
+        let mut theme = Self {
+            name: name.to_string(),
+            styles: HashMap::new(),
+        };
+        theme.load_into(name, false, 0)?;
+        Ok(theme)
+    }
+
+    /// Adds the styles of theme `name` and the themes it inherits from. A user
+    /// theme inheriting from its own name extends the bundled theme.
+    fn load_into(&mut self, name: &str, bundled_only: bool, depth: usize) -> Result<(), String> {
+        if depth > MAX_INHERITS {
+            return Err(format!("theme `{name}` inherits too deeply"));
+        }
+        let user_file = config_dir()
+            .filter(|_| !bundled_only)
+            .map(|dir| dir.join("themes").join(format!("{name}.toml")));
+        let (source, contents) = match user_file.filter(|path| path.is_file()) {
+            Some(path) => {
+                let contents = fs::read_to_string(&path)
+                    .map_err(|error| format!("{}: {error}", path.display()))?;
+                (path.display().to_string(), contents)
+            }
+            None => {
+                let (_, contents) = BUNDLED
+                    .iter()
+                    .find(|(bundled, _)| *bundled == name)
+                    .ok_or_else(|| format!("no theme named `{name}`"))?;
+                (format!("theme `{name}`"), contents.to_string())
+            }
+        };
+        let table = contents
+            .parse::<Table>()
+            .map_err(|error| format!("{source}: {}", error.message()))?;
+        if let Some(parent) = table.get("inherits") {
+            let parent = parent
+                .as_str()
+                .ok_or_else(|| format!("{source}: `inherits` must be a theme name"))?;
+            let bundled_only = parent == name;
+            self.load_into(parent, bundled_only, depth + 1)?;
+        }
+        for (name, value) in table.iter().filter(|(key, _)| *key != "inherits") {
+            self.set(name, value)
+                .map_err(|error| format!("{source}: `{name}` {error}"))?;
+        }
+        Ok(())
+    }
+
+    /// Overrides the properties of style `name` that `value` sets.
+    pub fn set(&mut self, name: &str, value: &Value) -> Result<(), String> {
+        if !STYLE_NAMES.contains(&name) {
+            return Err("is not a known style".to_string());
+        }
+        let style = Style::parse(value)?;
+        let entry = self.styles.entry(name.to_string()).or_default();
+        *entry = entry.patch(style);
+        Ok(())
+    }
+
+    /// The style `name`, merged with its prefixes and with `text`.
+    pub fn style(&self, name: &str) -> Style {
+        let mut style = self.styles.get("text").copied().unwrap_or_default();
+        let mut end = 0;
+        for part in name.split('.') {
+            end += part.len();
+            if let Some(own) = self.styles.get(&name[..end]) {
+                style = style.patch(*own);
+            }
+            end += 1;
+        }
+        style
+    }
+
+    /// Names of the bundled themes and of the ones in the config directory.
+    pub fn available() -> Vec<String> {
+        let mut names: Vec<String> = BUNDLED.iter().map(|(name, _)| name.to_string()).collect();
+        let user_themes = config_dir().and_then(|dir| fs::read_dir(dir.join("themes")).ok());
+        for entry in user_themes.into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+            {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// Parses a `"#rrggbb"` color or a named terminal color such as `"red"` or
+/// `"dark_grey"`.
+fn parse_color(value: &Value) -> Result<Color, String> {
+    let invalid = || "colors must look like \"#rrggbb\" or \"red\"".to_string();
+    let text = value.as_str().ok_or_else(invalid)?;
+    let Some(hex) = text.strip_prefix('#') else {
+        return Color::try_from(text).map_err(|()| invalid());
+    };
+    if hex.len() != 6 {
+        return Err(invalid());
+    }
+    let channel = |index: usize| {
+        hex.get(index..index + 2)
+            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+            .ok_or_else(invalid)
+    };
+    Ok(Color::Rgb {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+    })
+}
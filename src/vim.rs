@@ -0,0 +1,843 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{Document, Position};
+
+/// Commands of the `:` line besides the editor's actions.
+pub const EX_COMMANDS: &[(&str, &str)] = &[
+    ("e", "Edit <file>"),
+    ("grep", "Search the project for <text>"),
+    ("w", "Write the buffer, to <file> if given"),
+    ("wq", "Write the buffer and quit"),
+    ("x", "Write the buffer if modified and quit"),
+    ("q", "Quit unless there are unsaved changes"),
+    ("q!", "Quit without saving"),
+    ("qa", "Quit unless there are unsaved changes"),
+    ("qa!", "Quit without saving"),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+    Command,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "V-LINE",
+            Mode::Command => "COMMAND",
+        }
+    }
+
+    pub fn is_visual(self) -> bool {
+        matches!(self, Mode::Visual | Mode::VisualLine)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    /// `gg`: the first line, or line `count`.
+    FirstLine,
+    /// `G`: the last line, or line `count`.
+    LastLine,
+    FindForward(char),
+    TillForward(char),
+    FindBackward(char),
+    TillBackward(char),
+    /// The current line, as in `dd`.
+    Line,
+}
+
+impl Motion {
+    pub fn is_vertical(self) -> bool {
+        matches!(self, Motion::Up | Motion::Down)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+/// Where `i`, `a`, `o` and friends start inserting.
+#[derive(Clone, Copy)]
+pub enum Insert {
+    Before,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+/// What an operator acts on between the cursor and a motion's target.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Span {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+/// A complete normal or visual mode command. Counts are None when not typed.
+pub enum Command {
+    Move(Motion, Option<usize>),
+    Operate(Operator, Motion, Option<usize>),
+    /// An operator applied to the visual selection.
+    OperateSelection(Operator),
+    Insert(Insert),
+    Paste {
+        before: bool,
+        count: usize,
+    },
+    Undo(usize),
+    Redo(usize),
+    Repeat(usize),
+    Visual(Mode),
+    ExLine,
+    Escape,
+}
+
+impl Command {
+    /// Commands that change the text and can be repeated with `.`.
+    fn is_change(&self) -> bool {
+        match self {
+            Command::Operate(operator, _, _) => *operator != Operator::Yank,
+            Command::Insert(_) | Command::Paste { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+pub enum Parse {
+    Pending,
+    Invalid,
+    Done(Command),
+}
+
+/// Text yanked or deleted by the last operator.
+#[derive(Default)]
+pub struct Register {
+    pub text: String,
+    pub linewise: bool,
+}
+
+/// State of the Vim keybindings.
+pub struct Vim {
+    pub mode: Mode,
+    /// Keys of the command being typed.
+    pending: Vec<KeyEvent>,
+    pub visual_start: Position,
+    pub register: Register,
+    /// Keys of the change in progress, including text typed in insert mode.
+    recording: Option<Vec<KeyEvent>>,
+    last_change: Vec<KeyEvent>,
+    pub replaying: bool,
+}
+
+impl Default for Vim {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            visual_start: Position::default(),
+            register: Register::default(),
+            recording: None,
+            last_change: Vec::new(),
+            replaying: false,
+        }
+    }
+}
+
+impl Vim {
+    /// Adds a normal or visual mode key to the pending command.
+    pub fn parse(&mut self, key: KeyEvent) -> Parse {
+        self.pending.push(key);
+        let keys: Vec<Key> = self.pending.iter().map(|key| Key::from(*key)).collect();
+        let parse = parse(&keys, self.mode.is_visual());
+        match &parse {
+            Parse::Pending => return parse,
+            Parse::Done(command) if command.is_change() && !self.replaying => {
+                self.recording = Some(self.pending.clone());
+            }
+            _ => (),
+        }
+        self.pending.clear();
+        parse
+    }
+
+    /// Keys of the command typed so far, for the status bar.
+    pub fn pending(&self) -> String {
+        self.pending
+            .iter()
+            .map(|key| match key.code {
+                KeyCode::Char(c) => c.to_string(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Adds a key typed in insert mode to the change being recorded.
+    pub fn record(&mut self, key: KeyEvent) {
+        if let Some(keys) = &mut self.recording {
+            keys.push(key);
+        }
+    }
+
+    /// Ends the change being recorded, making it the one `.` repeats.
+    pub fn finish_change(&mut self) {
+        if let Some(keys) = self.recording.take() {
+            self.last_change = keys;
+        }
+    }
+
+    pub fn last_change(&self) -> Vec<KeyEvent> {
+        self.last_change.clone()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Escape,
+    Left,
+    Right,
+    Up,
+    Down,
+    Redo,
+    Other,
+}
+
+impl From<KeyEvent> for Key {
+    fn from(key: KeyEvent) -> Self {
+        // AltGr characters arrive with both Ctrl and Alt held.
+        let control = key.modifiers.contains(KeyModifiers::CONTROL)
+            && !key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Char('r') if control => Key::Redo,
+            KeyCode::Char('[') if control => Key::Escape,
+            KeyCode::Char(_) if control => Key::Other,
+            KeyCode::Char(c) => Key::Char(c),
+            KeyCode::Esc => Key::Escape,
+            KeyCode::Left | KeyCode::Backspace => Key::Left,
+            KeyCode::Right => Key::Right,
+            KeyCode::Up => Key::Up,
+            KeyCode::Down | KeyCode::Enter => Key::Down,
+            _ => Key::Other,
+        }
+    }
+}
+
+/// Splits a leading count off `keys`. A lone `0` is a motion, not a count.
+fn count(keys: &[Key]) -> (Option<usize>, &[Key]) {
+    let digits = keys
+        .iter()
+        .enumerate()
+        .take_while(|&(index, key)| match key {
+            Key::Char('0') => index > 0,
+            Key::Char(c) => c.is_ascii_digit(),
+            _ => false,
+        })
+        .count();
+    let count = keys[..digits]
+        .iter()
+        .filter_map(|key| match key {
+            Key::Char(c) => c.to_digit(10),
+            _ => None,
+        })
+        .fold(None, |count: Option<usize>, digit| {
+            Some(
+                count
+                    .unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(digit as usize),
+            )
+        });
+    (count, &keys[digits..])
+}
+
+fn multiply(first: Option<usize>, second: Option<usize>) -> Option<usize> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(first.saturating_mul(second)),
+        (first, second) => first.or(second),
+    }
+}
+
+fn parse(keys: &[Key], visual: bool) -> Parse {
+    let (count, rest) = count(keys);
+    let Some(&first) = rest.first() else {
+        return Parse::Pending;
+    };
+    let times = count.unwrap_or(1);
+    let operator = match first {
+        Key::Char('d' | 'x') => Some(Operator::Delete),
+        Key::Char('c' | 's') => Some(Operator::Change),
+        Key::Char('y') => Some(Operator::Yank),
+        _ => None,
+    };
+    if visual {
+        if let Some(operator) = operator {
+            return Parse::Done(Command::OperateSelection(operator));
+        }
+    }
+    let command = match first {
+        Key::Char(c @ ('d' | 'c' | 'y')) => {
+            let operator = operator.unwrap_or(Operator::Delete);
+            let (second_count, rest) = self::count(&rest[1..]);
+            let count = multiply(count, second_count);
+            return match rest {
+                [] => Parse::Pending,
+                [Key::Char(repeated), ..] if *repeated == c => {
+                    Parse::Done(Command::Operate(operator, Motion::Line, count))
+                }
+                _ => match motion(rest) {
+                    Parse::Done(Command::Move(motion, _)) => {
+                        Parse::Done(Command::Operate(operator, motion, count))
+                    }
+                    parse => parse,
+                },
+            };
+        }
+        Key::Char('x') => Command::Operate(Operator::Delete, Motion::Right, count),
+        Key::Char('X') => Command::Operate(Operator::Delete, Motion::Left, count),
+        Key::Char('s') => Command::Operate(Operator::Change, Motion::Right, count),
+        Key::Char('D') => Command::Operate(Operator::Delete, Motion::LineEnd, count),
+        Key::Char('C') => Command::Operate(Operator::Change, Motion::LineEnd, count),
+        Key::Char('Y') => Command::Operate(Operator::Yank, Motion::Line, count),
+        Key::Char('p') => Command::Paste {
+            before: false,
+            count: times,
+        },
+        Key::Char('P') => Command::Paste {
+            before: true,
+            count: times,
+        },
+        Key::Char('u') => Command::Undo(times),
+        Key::Redo => Command::Redo(times),
+        Key::Char('.') => Command::Repeat(times),
+        Key::Char('i') => Command::Insert(Insert::Before),
+        Key::Char('a') => Command::Insert(Insert::After),
+        Key::Char('I') => Command::Insert(Insert::LineStart),
+        Key::Char('A') => Command::Insert(Insert::LineEnd),
+        Key::Char('o') => Command::Insert(Insert::LineBelow),
+        Key::Char('O') => Command::Insert(Insert::LineAbove),
+        Key::Char('v') => Command::Visual(Mode::Visual),
+        Key::Char('V') => Command::Visual(Mode::VisualLine),
+        Key::Char(':') => Command::ExLine,
+        Key::Escape => Command::Escape,
+        _ => {
+            return match motion(rest) {
+                Parse::Done(Command::Move(motion, _)) => Parse::Done(Command::Move(motion, count)),
+                parse => parse,
+            }
+        }
+    };
+    if rest.len() > 1 {
+        return Parse::Invalid;
+    }
+    Parse::Done(command)
+}
+
+fn motion(keys: &[Key]) -> Parse {
+    let motion = match keys {
+        [] => return Parse::Pending,
+        [Key::Char('h') | Key::Left] => Motion::Left,
+        [Key::Char('l' | ' ') | Key::Right] => Motion::Right,
+        [Key::Char('k') | Key::Up] => Motion::Up,
+        [Key::Char('j') | Key::Down] => Motion::Down,
+        [Key::Char('w')] => Motion::WordForward,
+        [Key::Char('b')] => Motion::WordBackward,
+        [Key::Char('e')] => Motion::WordEnd,
+        [Key::Char('0')] => Motion::LineStart,
+        [Key::Char('^')] => Motion::FirstNonBlank,
+        [Key::Char('$')] => Motion::LineEnd,
+        [Key::Char('G')] => Motion::LastLine,
+        [Key::Char('g' | 'f' | 't' | 'F' | 'T')] => return Parse::Pending,
+        [Key::Char('g'), Key::Char('g')] => Motion::FirstLine,
+        [Key::Char('f'), Key::Char(c)] => Motion::FindForward(*c),
+        [Key::Char('t'), Key::Char(c)] => Motion::TillForward(*c),
+        [Key::Char('F'), Key::Char(c)] => Motion::FindBackward(*c),
+        [Key::Char('T'), Key::Char(c)] => Motion::TillBackward(*c),
+        _ => return Parse::Invalid,
+    };
+    Parse::Done(Command::Move(motion, None))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn classes(document: &Document, y: usize) -> Vec<Class> {
+    let Some(row) = document.row(y) else {
+        return Vec::new();
+    };
+    row.as_str()
+        .graphemes(true)
+        .map(|grapheme| {
+            if grapheme.trim().is_empty() {
+                Class::Blank
+            } else if grapheme.chars().any(|c| c.is_alphanumeric() || c == '_') {
+                Class::Word
+            } else {
+                Class::Punctuation
+            }
+        })
+        .collect()
+}
+
+fn row_len(document: &Document, y: usize) -> usize {
+    document.row(y).map_or(0, |row| row.len())
+}
+
+fn last_line(document: &Document) -> usize {
+    document.len().saturating_sub(1)
+}
+
+pub fn first_non_blank(document: &Document, y: usize) -> usize {
+    let line = classes(document, y);
+    line.iter()
+        .position(|class| *class != Class::Blank)
+        .unwrap_or(line.len())
+}
+
+/// Start of the next word, where an empty line also counts as a word.
+fn word_forward(document: &Document, from: Position) -> Position {
+    let Position { mut x, mut y } = from;
+    let mut line = classes(document, y);
+    if let Some(&class) = line.get(x).filter(|class| **class != Class::Blank) {
+        while line.get(x) == Some(&class) {
+            x += 1;
+        }
+    }
+    loop {
+        while line.get(x) == Some(&Class::Blank) {
+            x += 1;
+        }
+        if x < line.len() || y >= last_line(document) {
+            return Position { x, y };
+        }
+        y += 1;
+        x = 0;
+        line = classes(document, y);
+        if line.is_empty() {
+            return Position { x, y };
+        }
+    }
+}
+
+fn word_backward(document: &Document, from: Position) -> Position {
+    let Position { mut x, mut y } = from;
+    let mut line = classes(document, y);
+    loop {
+        if x == 0 {
+            if y == 0 {
+                return Position::default();
+            }
+            y -= 1;
+            line = classes(document, y);
+            x = line.len();
+            if line.is_empty() {
+                return Position { x, y };
+            }
+            continue;
+        }
+        x -= 1;
+        if line[x] != Class::Blank {
+            break;
+        }
+    }
+    let class = line[x];
+    while x > 0 && line[x - 1] == class {
+        x -= 1;
+    }
+    Position { x, y }
+}
+
+fn word_end(document: &Document, from: Position) -> Position {
+    let Position { mut x, mut y } = from;
+    let mut line = classes(document, y);
+    x += 1;
+    loop {
+        while line.get(x) == Some(&Class::Blank) {
+            x += 1;
+        }
+        if x < line.len() {
+            break;
+        }
+        if y >= last_line(document) {
+            return Position {
+                x: line.len().saturating_sub(1),
+                y,
+            };
+        }
+        y += 1;
+        x = 0;
+        line = classes(document, y);
+    }
+    let class = line[x];
+    while line.get(x + 1) == Some(&class) {
+        x += 1;
+    }
+    Position { x, y }
+}
+
+/// Where `cw` stops: like `ce`, but the first step only goes to the end of
+/// the word under the cursor.
+pub fn change_word_end(document: &Document, from: Position, count: Option<usize>) -> Position {
+    let line = classes(document, from.y);
+    let mut x = from.x;
+    if let Some(&class) = line.get(x) {
+        while line.get(x + 1) == Some(&class) {
+            x += 1;
+        }
+    }
+    (1..count.unwrap_or(1)).fold(Position { x, y: from.y }, |position, _| {
+        word_end(document, position)
+    })
+}
+
+/// Index of the `count`th occurrence of `c` after (or before) `x` in row `y`.
+fn find(
+    document: &Document,
+    y: usize,
+    x: usize,
+    c: char,
+    forward: bool,
+    count: usize,
+) -> Option<usize> {
+    let row = document.row(y)?;
+    let target = c.to_string();
+    let matches = row
+        .as_str()
+        .graphemes(true)
+        .enumerate()
+        .filter(|(_, grapheme)| *grapheme == target)
+        .map(|(index, _)| index);
+    if forward {
+        return matches.filter(|&index| index > x).nth(count - 1);
+    }
+    let before: Vec<usize> = matches.take_while(|&index| index < x).collect();
+    before.into_iter().rev().nth(count - 1)
+}
+
+/// Where `motion` takes the cursor from `from`, and the span an operator
+/// covers. None if the motion fails, like `f` without a match.
+pub fn target(
+    document: &Document,
+    from: Position,
+    motion: Motion,
+    count: Option<usize>,
+) -> Option<(Position, Span)> {
+    let times = count.unwrap_or(1).max(1);
+    let Position { x, y } = from;
+    let line_at = |y: usize| Position {
+        x: first_non_blank(document, y),
+        y,
+    };
+    let result = match motion {
+        Motion::Left => (
+            Position {
+                x: x.saturating_sub(times),
+                y,
+            },
+            Span::Exclusive,
+        ),
+        Motion::Right => (
+            Position {
+                x: x.saturating_add(times).min(row_len(document, y)),
+                y,
+            },
+            Span::Exclusive,
+        ),
+        Motion::Up => (
+            Position {
+                x,
+                y: y.saturating_sub(times),
+            },
+            Span::Linewise,
+        ),
+        Motion::Down => (
+            Position {
+                x,
+                y: y.saturating_add(times).min(last_line(document)),
+            },
+            Span::Linewise,
+        ),
+        Motion::WordForward => (
+            (0..times).fold(from, |position, _| word_forward(document, position)),
+            Span::Exclusive,
+        ),
+        Motion::WordBackward => (
+            (0..times).fold(from, |position, _| word_backward(document, position)),
+            Span::Exclusive,
+        ),
+        Motion::WordEnd => (
+            (0..times).fold(from, |position, _| word_end(document, position)),
+            Span::Inclusive,
+        ),
+        Motion::LineStart => (Position { x: 0, y }, Span::Exclusive),
+        Motion::FirstNonBlank => (line_at(y), Span::Exclusive),
+        Motion::LineEnd => {
+            let y = y.saturating_add(times - 1).min(last_line(document));
+            let x = row_len(document, y).saturating_sub(1);
+            (Position { x, y }, Span::Inclusive)
+        }
+        Motion::FirstLine => (
+            line_at(
+                count
+                    .unwrap_or(1)
+                    .saturating_sub(1)
+                    .min(last_line(document)),
+            ),
+            Span::Linewise,
+        ),
+        Motion::LastLine => {
+            let line = count.map_or(last_line(document), |line| line.saturating_sub(1));
+            (line_at(line.min(last_line(document))), Span::Linewise)
+        }
+        Motion::FindForward(c) => (
+            Position {
+                x: find(document, y, x, c, true, times)?,
+                y,
+            },
+            Span::Inclusive,
+        ),
+        Motion::TillForward(c) => (
+            Position {
+                x: find(document, y, x, c, true, times)? - 1,
+                y,
+            },
+            Span::Inclusive,
+        ),
+        Motion::FindBackward(c) => (
+            Position {
+                x: find(document, y, x, c, false, times)?,
+                y,
+            },
+            Span::Exclusive,
+        ),
+        Motion::TillBackward(c) => (
+            Position {
+                x: find(document, y, x, c, false, times)? + 1,
+                y,
+            },
+            Span::Exclusive,
+        ),
+        Motion::Line => (
+            Position {
+                x,
+                y: y.saturating_add(times - 1).min(last_line(document)),
+            },
+            Span::Linewise,
+        ),
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Types `keys` and returns what the last one completed, checking that
+    /// the ones before it left the command pending.
+    fn typed(vim: &mut Vim, keys: &str) -> Parse {
+        let mut keys = keys.chars().map(|c| KeyEvent::from(KeyCode::Char(c)));
+        let last = keys.next_back().unwrap();
+        for key in keys {
+            assert!(matches!(vim.parse(key), Parse::Pending));
+        }
+        vim.parse(last)
+    }
+
+    fn normal(keys: &str) -> Parse {
+        typed(&mut Vim::default(), keys)
+    }
+
+    #[test]
+    fn parse_motions_and_counts() {
+        assert!(matches!(
+            normal("w"),
+            Parse::Done(Command::Move(Motion::WordForward, None))
+        ));
+        assert!(matches!(
+            normal("12j"),
+            Parse::Done(Command::Move(Motion::Down, Some(12)))
+        ));
+        assert!(matches!(
+            normal("0"),
+            Parse::Done(Command::Move(Motion::LineStart, None))
+        ));
+        assert!(matches!(
+            normal("10G"),
+            Parse::Done(Command::Move(Motion::LastLine, Some(10)))
+        ));
+        assert!(matches!(
+            normal("gg"),
+            Parse::Done(Command::Move(Motion::FirstLine, None))
+        ));
+        assert!(matches!(
+            normal("2fx"),
+            Parse::Done(Command::Move(Motion::FindForward('x'), Some(2)))
+        ));
+        assert!(matches!(
+            normal("99999999999999999999999k"),
+            Parse::Done(Command::Move(Motion::Up, Some(usize::MAX)))
+        ));
+    }
+
+    #[test]
+    fn parse_operators() {
+        assert!(matches!(
+            normal("dd"),
+            Parse::Done(Command::Operate(Operator::Delete, Motion::Line, None))
+        ));
+        assert!(matches!(
+            normal("cc"),
+            Parse::Done(Command::Operate(Operator::Change, Motion::Line, None))
+        ));
+        assert!(matches!(
+            normal("3dw"),
+            Parse::Done(Command::Operate(
+                Operator::Delete,
+                Motion::WordForward,
+                Some(3)
+            ))
+        ));
+        assert!(matches!(
+            normal("2y3j"),
+            Parse::Done(Command::Operate(Operator::Yank, Motion::Down, Some(6)))
+        ));
+        assert!(matches!(
+            normal("d0"),
+            Parse::Done(Command::Operate(Operator::Delete, Motion::LineStart, None))
+        ));
+        assert!(matches!(
+            normal("ctx"),
+            Parse::Done(Command::Operate(
+                Operator::Change,
+                Motion::TillForward('x'),
+                None
+            ))
+        ));
+        assert!(matches!(
+            normal("4x"),
+            Parse::Done(Command::Operate(Operator::Delete, Motion::Right, Some(4)))
+        ));
+    }
+
+    #[test]
+    fn parse_other_commands() {
+        assert!(matches!(
+            normal("3p"),
+            Parse::Done(Command::Paste {
+                before: false,
+                count: 3
+            })
+        ));
+        assert!(matches!(normal("u"), Parse::Done(Command::Undo(1))));
+        assert!(matches!(normal("2."), Parse::Done(Command::Repeat(2))));
+        assert!(matches!(
+            normal("o"),
+            Parse::Done(Command::Insert(Insert::LineBelow))
+        ));
+        assert!(matches!(
+            normal("V"),
+            Parse::Done(Command::Visual(Mode::VisualLine))
+        ));
+        let redo = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL);
+        assert!(matches!(
+            Vim::default().parse(redo),
+            Parse::Done(Command::Redo(1))
+        ));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_keys() {
+        for keys in ["q", "dq", "gq", "dc", "2d3q"] {
+            let mut vim = Vim::default();
+            assert!(matches!(typed(&mut vim, keys), Parse::Invalid), "{keys}");
+            assert!(!vim.is_pending(), "{keys}");
+        }
+        let mut vim = Vim::default();
+        assert!(matches!(typed(&mut vim, "2d"), Parse::Pending));
+        assert_eq!(vim.pending(), "2d");
+        let escape = KeyEvent::from(KeyCode::Esc);
+        assert!(matches!(vim.parse(escape), Parse::Invalid));
+        assert!(!vim.is_pending());
+    }
+
+    #[test]
+    fn parse_visual_operators_act_on_the_selection() {
+        let mut vim = Vim {
+            mode: Mode::Visual,
+            ..Vim::default()
+        };
+        assert!(matches!(
+            typed(&mut vim, "d"),
+            Parse::Done(Command::OperateSelection(Operator::Delete))
+        ));
+        assert!(matches!(
+            typed(&mut vim, "3w"),
+            Parse::Done(Command::Move(Motion::WordForward, Some(3)))
+        ));
+    }
+
+    #[test]
+    fn altgr_characters_are_not_control_keys() {
+        let altgr = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        assert!(matches!(
+            Key::from(KeyEvent::new(KeyCode::Char('['), altgr)),
+            Key::Char('[')
+        ));
+        assert!(matches!(
+            Key::from(KeyEvent::new(KeyCode::Char('['), KeyModifiers::CONTROL)),
+            Key::Escape
+        ));
+    }
+
+    #[test]
+    fn changes_are_recorded_for_repeat() {
+        let mut vim = Vim::default();
+        typed(&mut vim, "cw");
+        vim.record(KeyEvent::from(KeyCode::Char('x')));
+        typed(&mut vim, "j");
+        vim.finish_change();
+        let keys: String = vim
+            .last_change()
+            .iter()
+            .filter_map(|key| match key.code {
+                KeyCode::Char(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(keys, "cwx");
+        typed(&mut vim, "yy");
+        vim.finish_change();
+        assert_eq!(vim.last_change().len(), 3);
+    }
+}